@@ -0,0 +1,169 @@
+//! ANSI escape codes helpers
+
+/// Removes ANSI escape sequences from a string
+pub fn strip(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediate bytes until a final byte
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: until BEL or ST
+            Some(']') => {
+                let mut last = '\0';
+                for c in chars.by_ref() {
+                    if c == '\x07' || (last == '\x1b' && c == '\\') {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            // other escapes are a single char
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Writer that removes the ANSI escape sequences, like [`strip`]
+///
+/// The sequences can be split between writes.
+pub struct StripWriter<W> {
+    inner: W,
+    state: StripState,
+}
+
+#[derive(Clone, Copy)]
+enum StripState {
+    Text,
+    /// After `ESC`
+    Escape,
+    /// Rest of a multi-byte char after `ESC`
+    EscapeChar,
+    /// After `ESC [`
+    Csi,
+    /// After `ESC ]`
+    Osc,
+    /// `ESC` inside an OSC
+    OscEscape,
+}
+
+impl<W> StripWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            state: StripState::Text,
+        }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for StripWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use StripState::*;
+        // start of the text not written yet
+        let mut start = None;
+        let mut i = 0;
+        while i < buf.len() {
+            let b = buf[i];
+            match self.state {
+                Text if b == 0x1b => {
+                    if let Some(s) = start.take() {
+                        self.inner.write_all(&buf[s..i])?;
+                    }
+                    self.state = Escape;
+                }
+                Text => {
+                    start.get_or_insert(i);
+                }
+                Escape => {
+                    self.state = match b {
+                        b'[' => Csi,
+                        b']' => Osc,
+                        0xc0.. => EscapeChar,
+                        _ => Text,
+                    }
+                }
+                EscapeChar if b & 0xc0 == 0x80 => {}
+                EscapeChar => {
+                    // not part of the char, process it again as text
+                    self.state = Text;
+                    continue;
+                }
+                Csi if (0x40..=0x7e).contains(&b) => self.state = Text,
+                Csi => {}
+                Osc | OscEscape if b == 0x07 => self.state = Text,
+                OscEscape if b == b'\\' => self.state = Text,
+                Osc | OscEscape if b == 0x1b => self.state = OscEscape,
+                Osc | OscEscape => self.state = Osc,
+            }
+            i += 1;
+        }
+        if let Some(s) = start {
+            self.inner.write_all(&buf[s..])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Width of a string in a terminal, without the ANSI escape sequences
+///
+/// Each char counts as one column.
+pub fn width(s: &str) -> usize {
+    strip(s).chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owo_colors::OwoColorize;
+
+    #[test]
+    fn strip_colors() {
+        let s = format!("{} and {}", "red".red().bold(), "dim".dimmed());
+        assert_eq!(strip(&s), "red and dim");
+        assert_eq!(strip("plain"), "plain");
+    }
+
+    #[test]
+    fn strip_writer() {
+        use std::io::Write;
+
+        let s = format!(
+            "{} é \x1b]8;;http://a\x1b\\link\x1b]8;;\x07 \x1bé{}",
+            "red".red().bold(),
+            "dim".dimmed()
+        );
+        // every split point, even inside sequences and chars
+        for split in 0..=s.len() {
+            let mut out = Vec::new();
+            let mut w = StripWriter::new(&mut out);
+            w.write_all(&s.as_bytes()[..split]).unwrap();
+            w.write_all(&s.as_bytes()[split..]).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                strip(&s),
+                "split at {split}"
+            );
+        }
+        assert_eq!(strip(&s), "red é link dim");
+    }
+
+    #[test]
+    fn width_without_colors() {
+        assert_eq!(width(&format!("{}!", "héllo".red().bold())), 6);
+    }
+}
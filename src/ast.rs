@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{borrow::Cow, rc::Rc};
 
 use crate::{eval::Eval, Error};
 
@@ -9,16 +9,74 @@ pub struct Query {
 
 #[derive(Debug, Clone)]
 pub struct Choose {
-    pub entries: Vec<(usize, Entry)>,
+    pub entries: Vec<ChooseEntry>,
     pub options: ChooseOptions,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
+pub struct ChooseEntry {
+    pub id: usize,
+    pub weight: f64,
+    pub entry: Entry,
+    /// Hash of the entry as written, see [`ChooseOptions::independent`]
+    pub key: u64,
+}
+
+impl ChooseEntry {
+    pub const DEFAULT_WEIGHT: f64 = 1.0;
+
+    pub fn weighted(id: usize, entry: Entry, weight: f64, key: u64) -> Self {
+        Self {
+            id,
+            weight,
+            entry,
+            key,
+        }
+    }
+}
+
+/// Stable hash of some text, FNV-1a
+///
+/// Unlike [`std::hash::DefaultHasher`], it's the same in every Rust version,
+/// so seeded results don't change.
+pub fn text_key(s: &str) -> u64 {
+    mix_key(0xcbf29ce484222325, s.as_bytes())
+}
+
+/// Continues a [`text_key`] with more bytes
+pub fn mix_key(mut key: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        key ^= *b as u64;
+        key = key.wrapping_mul(0x100000001b3);
+    }
+    key
+}
+
+#[derive(Debug, Clone)]
 pub struct ChooseOptions {
     pub repeating: bool,
+    /// Sort the selected entries by their position
+    ///
+    /// When repeating, the same entry drawn more than once keeps the draw
+    /// order.
     pub keep_order: bool,
     pub amount: Amount,
     pub text: bool,
+    pub unique: bool,
+    /// Evaluate each entry with its own generator
+    ///
+    /// The generator is seeded from a single draw and the entry as written, so
+    /// the result of an entry doesn't depend on the other entries or their
+    /// order.
+    pub independent: bool,
+    /// Join all the results in one, replacing each `%`
+    pub template: Option<Rc<str>>,
+    /// Also output the ids of the selected entries
+    pub debug: bool,
+    /// Join the same results in one with a count
+    pub tally: bool,
+    /// Output each kept die of the dice rolls as a result
+    pub split: bool,
 }
 
 impl Default for ChooseOptions {
@@ -28,6 +86,12 @@ impl Default for ChooseOptions {
             keep_order: false,
             amount: Amount::N(1),
             text: false,
+            unique: false,
+            independent: false,
+            template: None,
+            debug: false,
+            tally: false,
+            split: false,
         }
     }
 }
@@ -54,6 +118,8 @@ impl ChooseOptions {
 pub enum Amount {
     All,
     N(u32),
+    /// Random amount, both included
+    Range(u32, u32),
 }
 
 #[derive(Clone)]
@@ -73,11 +139,11 @@ impl std::fmt::Debug for Entry {
 
 impl Entry {
     pub fn parse(entry: &str) -> Result<Self, Error> {
-        let e = if let Some(expr) = crate::expr::parse_expr(entry)? {
+        let e = if let Some(expr) = crate::expr::parse_expr(entry.trim())? {
             Self::Expr(expr)
         } else {
             let s = clean_string(entry);
-            Self::data(s)
+            Self::data(&s)
         };
         Ok(e)
     }
@@ -85,19 +151,75 @@ impl Entry {
     pub fn data(entry: &str) -> Self {
         Self::Text(Rc::from(entry))
     }
+
+    pub fn text(&self) -> Option<&Rc<str>> {
+        match self {
+            Self::Text(t) => Some(t),
+            Self::Expr(_) => None,
+        }
+    }
 }
 
-fn clean_string(s: &str) -> &str {
+/// Removes the quotes of a quoted entry and processes its escapes
+///
+/// The escapes are `\n`, `\t`, `\r`, `\0`, `\\` and the quote. Other
+/// escapes are kept as written. Entries that are not quoted are not changed.
+fn clean_string(s: &str) -> Cow<'_, str> {
     if !s.starts_with(['\'', '"']) {
-        return s;
+        return s.into();
     }
     let delim = s.chars().next().unwrap();
-    if !s.ends_with(delim) || s.chars().nth_back(1).is_some_and(|c| c == '\\') {
-        return s;
+    if s.len() < 2 || !s.ends_with(delim) {
+        return s.into();
     }
     let content = &s[1..s.len() - 1];
-    if content.contains(delim) {
-        return s;
+    let mut out = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('0') => out.push('\0'),
+                Some(c) if c == '\\' || c == delim => out.push(c),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                // the closing quote is escaped
+                None => return s.into(),
+            },
+            c if c == delim => return s.into(),
+            c => out.push(c),
+        }
+    }
+    out.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(r#""line1\nline2""# => "line1\nline2" ; "new line")]
+    #[test_case(r#"'a\tb'"# => "a\tb" ; "tab")]
+    #[test_case(r#""say \"hi\"""# => "say \"hi\"" ; "quote")]
+    #[test_case(r#""back\\slash""# => "back\\slash" ; "backslash")]
+    #[test_case(r#""\d""# => "\\d" ; "unknown escape")]
+    #[test_case(r#"a\nb"# => "a\\nb" ; "not quoted")]
+    #[test_case(r#""a\""# => "\"a\\\"" ; "escaped end")]
+    #[test_case(r#""a"b""# => "\"a\"b\"" ; "inner quote")]
+    #[test_case("\"" => "\"" ; "single quote char")]
+    fn clean(s: &str) -> String {
+        clean_string(s).into_owned()
+    }
+
+    #[test]
+    fn escapes_in_query() {
+        let res = crate::run_query(r#""line1\nline2", a\nb, "x \", y" / all o"#).unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(values, ["line1\nline2", r"a\nb", r#"x ", y"#]);
+        assert_eq!(values[0].lines().count(), 2);
     }
-    content
 }
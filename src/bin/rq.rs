@@ -1,12 +1,14 @@
-use std::io::{self, BufRead, IsTerminal};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 
-use anstream::println;
+use anstream::{eprintln, println};
 use clap::{arg, command};
 use owo_colors::OwoColorize;
-use rng_query::State;
+use rng_query::{Algorithm, Sample, State};
 
-pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = command!()
+/// The CLI definition, also used to generate the completions
+fn cli() -> clap::Command {
+    command!()
         .arg(arg!([query] "Query to evaluate"))
         .arg(
             arg!(-q --quiet "Quiet, only show the selected values")
@@ -14,14 +16,93 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .alias("hide-expr")
                 .short_alias('E'),
         )
+        .arg(arg!(-p --pretty "Show a detailed breakdown of the results").conflicts_with("quiet"))
+        .arg(
+            arg!(--format <FORMAT> "Output format")
+                .default_value("text")
+                .value_parser(["text", "csv"])
+                .conflicts_with_all(["quiet", "pretty"]),
+        )
+        .arg(
+            arg!(--"json-lines" "Output a JSON object for each result, one per line")
+                .visible_alias("jsonl")
+                .conflicts_with_all(["pretty", "format"]),
+        )
+        .arg(
+            arg!(--columns [WIDTH] "Show the results in columns that fit in WIDTH, like ls")
+                .value_parser(clap::value_parser!(usize))
+                .require_equals(true)
+                .conflicts_with_all(["pretty", "format", "json-lines"]),
+        )
         .arg(arg!(-e --eval "Evaluate STDIN lines as expressions").alias("eval-stdin"))
-        .arg(arg!(--seed <SEED> "Seed the pseudorandom generator"))
+        .arg(
+            arg!(--"input-format" <FORMAT> "Format of the STDIN lines")
+                .default_value("lines")
+                .value_parser(["lines", "csv", "tsv"]),
+        )
+        .arg(
+            arg!(--column <N> "Column of the CSV/TSV input used as the entry, from 1")
+                .default_value("1")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--weights <FILE> "Read weighted entries from FILE, each line is WEIGHT<TAB>ENTRY")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--seed <SEED> "Seed the pseudorandom generator")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            arg!(--rng <ALGORITHM> "Pseudorandom generator algorithm")
+                .default_value("pcg")
+                .value_parser(["pcg", "chacha"]),
+        )
+        .arg(
+            arg!(--"best-of" <N> "Run the query N times and show the biggest numeric result")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--min <N> "Run the query again until the numeric result is at least N")
+                .value_parser(clap::value_parser!(f64))
+                .allow_negative_numbers(true),
+        )
+        .arg(
+            arg!(--max <N> "Run the query again until the numeric result is at most N")
+                .value_parser(clap::value_parser!(f64))
+                .allow_negative_numbers(true),
+        )
+        .arg(arg!(--advantage "Roll the single die of the query twice and keep the highest"))
+        .arg(
+            arg!(--disadvantage "Roll the single die of the query twice and keep the lowest")
+                .conflicts_with("advantage"),
+        )
+        .arg(arg!(--"show-seed" "Print the seed and a command to repeat the query to STDERR"))
+        .arg(arg!(-v --verbose "Print every random draw to STDERR"))
+        .arg(
+            arg!(--"max-output" <N> "Print at most N results, the rest are still generated")
+                .value_parser(clap::value_parser!(usize)),
+        )
         .arg(
             arg!(--color <WHEN> "Controls when to use color")
                 .default_value("auto")
                 .value_parser(clap::builder::EnumValueParser::<clap::ColorChoice>::new()),
         )
-        .get_matches();
+        .arg(arg!(--ascii "Only use ASCII for coins and colors, like H/T and #FF8000"))
+        .arg(
+            arg!(--completions <SHELL> "Print the completion script for a shell")
+                .value_parser(clap::value_parser!(clap_complete::Shell))
+                .hide(true),
+        )
+}
+
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = cli().get_matches();
+
+    if let Some(&shell) = matches.get_one::<clap_complete::Shell>("completions") {
+        clap_complete::generate(shell, &mut cli(), "rq", &mut io::stdout());
+        return Ok(());
+    }
 
     let color = match matches
         .get_one::<clap::ColorChoice>("color")
@@ -33,50 +114,620 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     color.write_global();
 
-    let seed = matches.get_one::<u64>("seed").copied();
+    let show_seed = matches.get_flag("show-seed");
+    let seed = matches
+        .get_one::<u64>("seed")
+        .copied()
+        .or_else(|| show_seed.then(rand::random));
+    let algorithm = match matches
+        .get_one::<String>("rng")
+        .expect("default rng value")
+        .as_str()
+    {
+        "chacha" => Algorithm::ChaCha,
+        _ => Algorithm::Pcg,
+    };
     let query = matches.get_one::<String>("query");
     let eval_stdin = matches.get_flag("eval");
     let quiet = matches.get_flag("quiet");
-
-    let mut state = if let Some(seed) = seed {
-        State::with_seed(seed)
+    let verbose = matches.get_flag("verbose");
+    let pretty = matches.get_flag("pretty");
+    let csv = matches
+        .get_one::<String>("format")
+        .is_some_and(|f| f == "csv");
+    let style = if matches.contains_id("columns") {
+        let width = matches
+            .get_one::<usize>("columns")
+            .copied()
+            .unwrap_or_else(terminal_width);
+        Style::Columns { width, quiet }
+    } else if matches.get_flag("json-lines") {
+        Style::JsonLines { quiet }
+    } else if csv {
+        Style::Csv
+    } else if quiet {
+        Style::Quiet
+    } else if pretty {
+        Style::Pretty
     } else {
-        State::new()
+        Style::Normal
+    };
+    let max_output = matches.get_one::<usize>("max-output").copied();
+    let best_of = matches.get_one::<u64>("best-of").copied();
+    let min = matches.get_one::<f64>("min").copied();
+    let max = matches.get_one::<f64>("max").copied();
+    let advantage = matches.get_flag("advantage");
+    let disadvantage = matches.get_flag("disadvantage");
+    let delimiter = match matches
+        .get_one::<String>("input-format")
+        .expect("default input format")
+        .as_str()
+    {
+        "csv" => Some(','),
+        "tsv" => Some('\t'),
+        _ => None,
     };
+    let column = *matches.get_one::<u64>("column").expect("default column") as usize - 1;
+
+    let mut state = State::with_algorithm(algorithm, seed);
+    state.set_ascii(matches.get_flag("ascii"));
+    state.set_truecolor(truecolor());
+
+    let mut inputs = Inputs {
+        weighted: Vec::new(),
+        lines: Vec::new(),
+        eval: eval_stdin,
+    };
+    if let Some(path) = matches.get_one::<PathBuf>("weights") {
+        inputs.weighted = read_weights(path)?;
+    }
 
     let stdin = io::stdin();
     if query.is_none() || !stdin.is_terminal() {
-        for line in stdin.lock().lines() {
+        for (n, line) in stdin.lock().lines().enumerate() {
+            // `lines` already strips the line ending, only the selected field
+            // is trimmed so empty fields at the start are kept
             let line = line?;
-            let line = line.trim();
-            if line.is_empty() {
+            if line.trim().is_empty() {
                 continue;
             }
-            if eval_stdin {
-                state.add_entry(line)?;
-            } else {
-                state.add_data(line);
+            let entry = match delimiter {
+                Some(delimiter) => input_field(&line, delimiter, column)
+                    .ok_or_else(|| format!("line {} has no column {}", n + 1, column + 1))?,
+                None => line,
+            };
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
             }
+            inputs.lines.push(entry.to_string());
         }
     }
+    // fail with bad entries before running anything
+    inputs.add_to(&mut State::new())?;
+    let piped = !inputs.lines.is_empty();
 
-    let input = match query {
-        Some(q) => q,
-        None => "",
+    let mut input = match query {
+        Some(q) => q.clone(),
+        None => String::new(),
+    };
+    if advantage || disadvantage {
+        input = roll_twice(&input, disadvantage)
+            .ok_or("--advantage and --disadvantage need a query with a single die, like 1d20")?;
+    }
+
+    let mut run = || -> Result<Vec<Sample>, Box<dyn std::error::Error>> {
+        inputs.add_to(&mut state)?;
+        let res = if verbose {
+            state.run_query_traced(&input, |msg| eprintln!("{} {msg}", "trace:".dimmed()))
+        } else {
+            state.run_query(&input)
+        };
+        Ok(res?)
+    };
+    let mut run = || {
+        if min.is_some() || max.is_some() {
+            run_bounded(min, max, &mut run)
+        } else {
+            run()
+        }
+    };
+    let res = match best_of {
+        Some(n) => run_best_of(n, run),
+        None => run(),
     };
 
-    match state.run_query(input) {
+    match res {
         Ok(output) => {
-            for sample in &output {
-                if quiet {
-                    println!("{sample:#}");
-                } else {
-                    println!("{sample}");
-                }
+            let hidden = write_output(&mut anstream::stdout().lock(), &output, style, max_output)?;
+            if hidden > 0 {
+                eprintln!("{}: {}", "warning".yellow(), truncated_warning(hidden));
             }
         }
         Err(err) => println!("{}: {err}", "error".red()),
     }
 
+    if let Some(seed) = seed.filter(|_| show_seed) {
+        if quiet {
+            eprintln!("{seed}");
+        } else {
+            let mut args = vec![];
+            if algorithm == Algorithm::ChaCha {
+                args.extend(["--rng".to_string(), "chacha".to_string()]);
+            }
+            if let Some(n) = best_of {
+                args.extend(["--best-of".to_string(), n.to_string()]);
+            }
+            if let Some(min) = min {
+                args.extend(["--min".to_string(), min.to_string()]);
+            }
+            if let Some(max) = max {
+                args.extend(["--max".to_string(), max.to_string()]);
+            }
+            if let Some(path) = matches.get_one::<PathBuf>("weights") {
+                let path = path.to_string_lossy();
+                args.extend(["--weights".to_string(), shell_quote(&path)]);
+            }
+            if piped {
+                if eval_stdin {
+                    args.push("-e".to_string());
+                }
+                if let Some(format) = matches
+                    .get_one::<String>("input-format")
+                    .filter(|f| *f != "lines")
+                {
+                    args.extend(["--input-format".to_string(), format.clone()]);
+                    args.extend(["--column".to_string(), (column + 1).to_string()]);
+                }
+            }
+            let footer = seed_footer(seed, &args, query.map(|_| input.as_str()), piped);
+            eprintln!("{}", footer.dimmed());
+        }
+    }
+
     Ok(())
 }
+
+#[derive(Debug, Clone, Copy)]
+enum Style {
+    Normal,
+    Quiet,
+    Pretty,
+    /// Header and a row for each result
+    Csv,
+    /// A JSON object for each result, or only the value if `quiet`
+    JsonLines {
+        quiet: bool,
+    },
+    /// Aligned columns, only the values if `quiet`
+    Columns {
+        width: usize,
+        quiet: bool,
+    },
+}
+
+/// Runs a query `n` times and keeps the output with the biggest result
+///
+/// Each run must give a single numeric result. With ties, the first one is
+/// kept.
+fn run_best_of(
+    n: u64,
+    mut run: impl FnMut() -> Result<Vec<Sample>, Box<dyn std::error::Error>>,
+) -> Result<Vec<Sample>, Box<dyn std::error::Error>> {
+    let mut best: Option<(f64, Vec<Sample>)> = None;
+    for _ in 0..n {
+        let output = run()?;
+        let value =
+            single_number(&output).ok_or("--best-of needs a query with a single numeric result")?;
+        if best.as_ref().is_none_or(|(max, _)| value > *max) {
+            best = Some((value, output));
+        }
+    }
+    Ok(best.map(|(_, output)| output).unwrap_or_default())
+}
+
+/// The query rolling the single die of `query` twice, keeping the highest or
+/// the lowest with `keep_low`
+///
+/// Only for a single die with optional modifiers, like `d20` or `1d20+5`, so
+/// `1d20+5` becomes `2d20kh1+5`.
+fn roll_twice(query: &str, keep_low: bool) -> Option<String> {
+    let re =
+        regex::Regex::new(r"\A\s*1?\s*d\s*(\d+|%)\s*((?:[+-]\s*\d+\s*)*)\z").expect("valid regex");
+    let caps = re.captures(query)?;
+    let keep = if keep_low { "kl1" } else { "kh1" };
+    let modifiers = caps[2].replace(char::is_whitespace, "");
+    Some(format!("2d{}{keep}{modifiers}", &caps[1]))
+}
+
+/// Max runs of the query for `--min` and `--max`
+const MAX_ATTEMPTS: u32 = 1000;
+
+/// Runs a query until its result is between `min` and `max`, both inclusive
+///
+/// Each run must give a single numeric result. Gives up with an error after
+/// [`MAX_ATTEMPTS`] runs.
+fn run_bounded(
+    min: Option<f64>,
+    max: Option<f64>,
+    mut run: impl FnMut() -> Result<Vec<Sample>, Box<dyn std::error::Error>>,
+) -> Result<Vec<Sample>, Box<dyn std::error::Error>> {
+    for _ in 0..MAX_ATTEMPTS {
+        let output = run()?;
+        let value = single_number(&output)
+            .ok_or("--min and --max need a query with a single numeric result")?;
+        if min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max) {
+            return Ok(output);
+        }
+    }
+    Err(format!("no result in bounds after {MAX_ATTEMPTS} attempts").into())
+}
+
+/// The value of the only sample in `output`, if it's a number
+fn single_number(output: &[Sample]) -> Option<f64> {
+    match output {
+        [sample] => sample.value().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Width from the `COLUMNS` env var, or 80
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(80)
+}
+
+/// Writes the results, one per line
+///
+/// Returns how many results were not written because of `max`.
+fn write_output(
+    w: &mut impl Write,
+    output: &[Sample],
+    style: Style,
+    max: Option<usize>,
+) -> io::Result<usize> {
+    let max = max.unwrap_or(usize::MAX);
+    if let Style::Csv = style {
+        writeln!(w, "index,kind,expression,value")?;
+    }
+    if let Style::Columns { width, quiet } = style {
+        let shown = &output[..output.len().min(max)];
+        let columns = rng_query::display_columns(shown, width);
+        if quiet {
+            write!(w, "{columns:#}")?;
+        } else {
+            write!(w, "{columns}")?;
+        }
+        return Ok(output.len() - shown.len());
+    }
+    for (i, sample) in output.iter().take(max).enumerate() {
+        match style {
+            Style::Normal => writeln!(w, "{sample}")?,
+            Style::Quiet => writeln!(w, "{sample:#}")?,
+            Style::Pretty => writeln!(w, "{}", sample.pretty())?,
+            Style::Csv => writeln!(
+                w,
+                "{i},{},{},{}",
+                sample.kind(),
+                csv_field(&sample.expression().unwrap_or_default()),
+                csv_field(&sample.value())
+            )?,
+            Style::Columns { .. } => unreachable!("written before"),
+            Style::JsonLines { quiet: true } => writeln!(w, "{}", json_string(&sample.value()))?,
+            Style::JsonLines { quiet: false } => {
+                write!(
+                    w,
+                    r#"{{"index":{i},"kind":{},"expression":{},"value":{}"#,
+                    json_string(sample.kind()),
+                    sample
+                        .expression()
+                        .map_or_else(|| "null".to_string(), |e| json_string(&e)),
+                    json_string(&sample.value())
+                )?;
+                if let Some((r, g, b)) = sample.rgb() {
+                    write!(w, r#","rgb":[{r},{g},{b}]"#)?;
+                }
+                writeln!(w, "}}")?;
+            }
+        }
+    }
+    Ok(output.len().saturating_sub(max))
+}
+
+/// Quotes a CSV field if needed
+fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
+
+/// Quoted and escaped JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Entries from the weights file and STDIN
+///
+/// Running a query takes the entries of the state, so they are added again
+/// before each run.
+struct Inputs {
+    weighted: Vec<(String, f64)>,
+    lines: Vec<String>,
+    /// Evaluate the lines as expressions
+    eval: bool,
+}
+
+impl Inputs {
+    fn add_to(&self, state: &mut State) -> Result<(), rng_query::Error> {
+        for (entry, weight) in &self.weighted {
+            state.add_weighted_data(entry, *weight);
+        }
+        for line in &self.lines {
+            if self.eval {
+                state.add_entry(line)?;
+            } else {
+                state.add_data(line);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the lines of the weights file, each is `WEIGHT<TAB>ENTRY`
+///
+/// Blank lines are skipped. The weights can be decimal numbers, but not
+/// negative.
+fn read_weights(path: &Path) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let mut weighted = Vec::new();
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    for (n, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (weight, entry) = line
+            .split_once('\t')
+            .ok_or_else(|| format!("line {} of the weights has no tab", n + 1))?;
+        let weight = weight
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|w| w.is_finite() && *w >= 0.0)
+            .ok_or_else(|| format!("line {} of the weights has a bad weight: {weight}", n + 1))?;
+        weighted.push((entry.to_string(), weight));
+    }
+    Ok(weighted)
+}
+
+/// Field `column` of a CSV/TSV line
+///
+/// Fields can be quoted with `"`, and `""` is a quote inside them.
+fn input_field(line: &str, delimiter: char, column: usize) -> Option<String> {
+    let mut current = 0;
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.next_if_eq(&'"').is_some() => field.push('"'),
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if c == delimiter && !quoted => {
+                if current == column {
+                    return Some(field);
+                }
+                current += 1;
+                field.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    (current == column).then_some(field)
+}
+
+fn truncated_warning(hidden: usize) -> String {
+    let s = if hidden == 1 { "" } else { "s" };
+    format!("output truncated, {hidden} more result{s} not shown")
+}
+
+/// Seed and a command to repeat the query
+///
+/// `args` are every other arg that changes the result, already quoted. If the
+/// entries were `piped` from STDIN, the input itself is not included, so the
+/// footer says it has to be piped again.
+fn seed_footer(seed: u64, args: &[String], query: Option<&str>, piped: bool) -> String {
+    let mut footer = format!("seed: {seed}\nrq --seed {seed}");
+    for arg in args {
+        footer.push(' ');
+        footer.push_str(arg);
+    }
+    if let Some(query) = query {
+        footer.push(' ');
+        footer.push_str(&shell_quote(query));
+    }
+    if piped {
+        footer.push_str("\n(pipe the same STDIN input again)");
+    }
+    footer
+}
+
+/// If the terminal says it supports 24 bit colors
+fn truecolor() -> bool {
+    std::env::var("COLORTERM").is_ok_and(|v| v == "truecolor" || v == "24bit")
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal CSV parser, only what [`csv_field`] outputs
+    fn parse_csv(s: &str) -> Vec<Vec<String>> {
+        let mut rows = vec![vec![]];
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => rows.last_mut().unwrap().push(std::mem::take(&mut field)),
+                '\n' if !quoted => {
+                    rows.last_mut().unwrap().push(std::mem::take(&mut field));
+                    rows.push(vec![]);
+                }
+                c => field.push(c),
+            }
+        }
+        rows.pop(); // after last new line
+        rows
+    }
+
+    #[test]
+    fn csv() {
+        let mut state = State::with_seed(1);
+        let query = r#"a, "b, c", {x, y / list fmt "say \"%\""}, 2d6 / all o"#;
+        let output = state.run_query(query).unwrap();
+
+        let mut buf = Vec::new();
+        write_output(&mut buf, &output, Style::Csv, None).unwrap();
+        let rows = parse_csv(&String::from_utf8(buf).unwrap());
+
+        assert_eq!(rows.len(), 1 + output.len());
+        assert_eq!(rows[0], ["index", "kind", "expression", "value"]);
+        assert_eq!(rows[1], ["0", "text", "", "a"]);
+        assert_eq!(rows[2], ["1", "text", "", "b, c"]);
+        assert_eq!(rows[3], ["2", "template", "say \"%\"", "say \"x, y\""]);
+        assert_eq!(rows[4][..3], ["3", "dice", "2d6"]);
+        assert_eq!(rows[4][3], output[3].value());
+    }
+
+    #[test]
+    fn completions() {
+        cli().debug_assert();
+        let mut buf = Vec::new();
+        clap_complete::generate(clap_complete::Shell::Bash, &mut cli(), "rq", &mut buf);
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.contains("--seed"));
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut state = State::with_seed(1);
+        let query = "a, \"b \\\"c\\\"\", {x, y / list fmt \"%\ttab\"}, 2d6 / all o";
+        let output = state.run_query(query).unwrap();
+
+        let mut buf = Vec::new();
+        write_output(&mut buf, &output, Style::JsonLines { quiet: false }, None).unwrap();
+        let lines = String::from_utf8(buf).unwrap();
+        let values = lines
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(values.len(), output.len());
+        assert_eq!(
+            values[0],
+            serde_json::json!({"index": 0, "kind": "text", "expression": null, "value": "a"})
+        );
+        assert_eq!(values[1]["value"], output[1].value());
+        assert_eq!(values[2]["value"], "x, y\ttab");
+        assert_eq!(values[3]["kind"], "dice");
+        assert_eq!(values[3]["expression"], "2d6");
+        assert!(values[3].get("rgb").is_none());
+
+        let output = state.run_query("color").unwrap();
+        let mut buf = Vec::new();
+        write_output(&mut buf, &output, Style::JsonLines { quiet: false }, None).unwrap();
+        let value =
+            serde_json::from_str::<serde_json::Value>(&String::from_utf8(buf).unwrap()).unwrap();
+        let (r, g, b) = output[0].rgb().unwrap();
+        assert_eq!(value["kind"], "color");
+        assert_eq!(value["rgb"], serde_json::json!([r, g, b]));
+
+        let mut buf = Vec::new();
+        write_output(&mut buf, &output, Style::JsonLines { quiet: true }, None).unwrap();
+        let lines = String::from_utf8(buf).unwrap();
+        for (line, sample) in lines.lines().zip(&output) {
+            let value = serde_json::from_str::<serde_json::Value>(line).unwrap();
+            assert_eq!(value, sample.value());
+        }
+    }
+
+    #[test]
+    fn input_columns() {
+        let line = r#"1,"Smith, John","say ""hi""",,last"#;
+        assert_eq!(input_field(line, ',', 0).unwrap(), "1");
+        assert_eq!(input_field(line, ',', 1).unwrap(), "Smith, John");
+        assert_eq!(input_field(line, ',', 2).unwrap(), r#"say "hi""#);
+        assert_eq!(input_field(line, ',', 3).unwrap(), "");
+        assert_eq!(input_field(line, ',', 4).unwrap(), "last");
+        assert_eq!(input_field(line, ',', 5), None);
+        assert_eq!(input_field("a\tb, c", '\t', 1).unwrap(), "b, c");
+    }
+
+    #[test]
+    fn max_output() {
+        let output = rng_query::run_query("a, b, c, d / list").unwrap();
+
+        let mut buf = Vec::new();
+        let hidden = write_output(&mut buf, &output, Style::Quiet, Some(2)).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "a\nb\n");
+        assert_eq!(hidden, 2);
+        assert_eq!(
+            truncated_warning(hidden),
+            "output truncated, 2 more results not shown"
+        );
+
+        let mut buf = Vec::new();
+        let hidden = write_output(&mut buf, &output, Style::Quiet, None).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "a\nb\nc\nd\n");
+        assert_eq!(hidden, 0);
+    }
+
+    #[test]
+    fn footer() {
+        let footer = seed_footer(12345, &[], Some("a, b / 2"), false);
+        assert_eq!(footer, "seed: 12345\nrq --seed 12345 'a, b / 2'");
+        assert_eq!(seed_footer(1, &[], None, false), "seed: 1\nrq --seed 1");
+        assert!(seed_footer(1, &[], Some("it's"), false).ends_with(r"'it'\''s'"));
+        let args = ["--rng", "chacha", "--best-of", "3"].map(String::from);
+        assert_eq!(
+            seed_footer(1, &args, Some("d6"), false),
+            "seed: 1\nrq --seed 1 --rng chacha --best-of 3 'd6'"
+        );
+        let args = ["-e".to_string()];
+        assert_eq!(
+            seed_footer(1, &args, Some("/ 2 r"), true),
+            "seed: 1\nrq --seed 1 -e '/ 2 r'\n(pipe the same STDIN input again)"
+        );
+        assert_eq!(
+            seed_footer(1, &[], None, true),
+            "seed: 1\nrq --seed 1\n(pipe the same STDIN input again)"
+        );
+    }
+}
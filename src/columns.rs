@@ -0,0 +1,125 @@
+//! Results laid out in columns
+
+use std::fmt::Display;
+
+use crate::{ansi, Sample};
+
+/// Displays the samples in aligned columns, like `ls`
+///
+/// The samples go down each column and then across, using as many columns as
+/// fit in `width`. ANSI escape codes don't count for the width. With the
+/// [alternate modifier](std::fmt#sign0), only the values are shown.
+///
+/// ```
+/// let output = rng_query::run_query("a, bb, ccc, d / list")?;
+/// let text = rng_query::display_columns(&output, 10).to_string();
+/// assert_eq!(text, "a    ccc\nbb   d\n");
+/// # Ok::<(), rng_query::Error>(())
+/// ```
+pub fn display_columns(samples: &[Sample], width: usize) -> impl Display + '_ {
+    Columns { samples, width }
+}
+
+/// Spaces between columns
+const GAP: usize = 3;
+
+struct Columns<'a> {
+    samples: &'a [Sample],
+    width: usize,
+}
+
+impl Display for Columns<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cells = self
+            .samples
+            .iter()
+            .map(|s| {
+                if f.alternate() {
+                    format!("{s:#}")
+                } else {
+                    s.to_string()
+                }
+            })
+            .collect::<Vec<_>>();
+        let widths = cells.iter().map(|c| ansi::width(c)).collect::<Vec<_>>();
+        let columns = pack(&widths, self.width);
+        if columns.is_empty() {
+            return Ok(());
+        }
+        let rows = cells.len().div_ceil(columns.len());
+        for row in 0..rows {
+            for (col, col_width) in columns.iter().enumerate() {
+                let i = col * rows + row;
+                let Some(cell) = cells.get(i) else { break };
+                f.write_str(cell)?;
+                // no trailing spaces
+                if cells.get(i + rows).is_some() {
+                    let pad = col_width - widths[i] + GAP;
+                    write!(f, "{:pad$}", "")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Width of each column to fit the cells in `width`
+///
+/// Uses the most columns possible, but always at least one even if a cell
+/// doesn't fit.
+fn pack(widths: &[usize], width: usize) -> Vec<usize> {
+    if widths.is_empty() {
+        return vec![];
+    }
+    for n in (2..=widths.len()).rev() {
+        let rows = widths.len().div_ceil(n);
+        let columns = widths
+            .chunks(rows)
+            .map(|c| c.iter().copied().max().unwrap_or_default())
+            .collect::<Vec<_>>();
+        // fewer columns than asked can happen, it's the same as a lower n
+        if columns.len() != n {
+            continue;
+        }
+        let total = columns.iter().sum::<usize>() + GAP * (columns.len() - 1);
+        if total <= width {
+            return columns;
+        }
+    }
+    vec![widths.iter().copied().max().unwrap_or_default()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(&[1, 1, 1, 1], 80 => vec![1, 1, 1, 1] ; "one row")]
+    #[test_case(&[1, 1, 1, 1], 5 => vec![1, 1] ; "two columns")]
+    #[test_case(&[1, 1, 1, 1], 1 => vec![1] ; "one column")]
+    #[test_case(&[10, 1, 1, 1, 1], 16 => vec![10, 1] ; "wide first")]
+    #[test_case(&[5, 5, 5], 4 => vec![5] ; "too wide")]
+    #[test_case(&[], 80 => Vec::<usize>::new() ; "empty")]
+    fn packing(widths: &[usize], width: usize) -> Vec<usize> {
+        pack(widths, width)
+    }
+
+    #[test]
+    fn layout() {
+        let output = crate::run_query("a, b, c, d, e / list").unwrap();
+        let text = display_columns(&output, 9).to_string();
+        assert_eq!(text, "a   c   e\nb   d\n");
+        let text = display_columns(&output, 8).to_string();
+        assert_eq!(text, "a   d\nb   e\nc\n");
+    }
+
+    #[test]
+    fn ignores_colors() {
+        let output = crate::run_query("coin, coin / all r").unwrap();
+        let text = display_columns(&output, 80).to_string();
+        let plain = ansi::strip(&text);
+        let values = output.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(plain, format!("{}   {}\n", values[0], values[1]));
+    }
+}
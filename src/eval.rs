@@ -1,16 +1,20 @@
-use std::rc::Rc;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use rand::seq::SliceRandom;
+use owo_colors::OwoColorize;
+use rand::{seq::SliceRandom, Rng, RngCore};
 
 use crate::{
-    ast::{Amount, Choose, ChooseOptions, Entry, Query},
-    Pcg,
+    ast::{mix_key, Amount, Choose, ChooseEntry, ChooseOptions, Entry, Query},
+    expr::interval::Num,
+    format::NumberFormat,
+    rng::Generator,
+    Error,
 };
 
 /// A sample from a selected entry
 ///
-/// This is an opaque type, hidden intentionally. It only expose the [`Display`]
-/// implementation to access it. The
+/// This is an opaque type, hidden intentionally. It mostly exposes the
+/// [`Display`] implementation to access it. The
 /// [`Display`] [alternate modifier](std::fmt#sign0) will only print the sampled
 /// value and not the whole representation.
 ///
@@ -19,18 +23,220 @@ pub struct Sample(SampleData);
 
 enum SampleData {
     Text(Rc<str>),
-    Expr(Box<dyn std::fmt::Display>),
+    Expr(Box<dyn ExprSample>),
+}
+
+/// Sample from an expression
+pub(crate) trait ExprSample: std::fmt::Display {
+    /// Name of the expression, see [`Sample::kind`]
+    fn kind(&self) -> &'static str;
+
+    /// Expression that generated the sample, may have ANSI escape codes
+    fn expression(&self) -> String;
+
+    /// Channels of the color, see [`Sample::rgb`]
+    fn rgb(&self) -> Option<(u8, u8, u8)> {
+        None
+    }
+
+    /// Numeric value, see [`Sample::number`]
+    fn number(&self) -> Option<Num> {
+        None
+    }
+
+    /// Single die before the modifiers, see [`Sample::natural`]
+    fn natural(&self) -> Option<u16> {
+        None
+    }
+
+    /// A sample for each die, for the `split` option
+    ///
+    /// Only dice rolls can be split.
+    fn split_dice(&self) -> Option<Vec<Sample>> {
+        None
+    }
+
+    /// Detailed representation, can take multiple lines
+    fn fmt_pretty(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt(f)
+    }
 }
 
 impl Sample {
     pub(crate) fn text(data: Rc<str>) -> Self {
         Self(SampleData::Text(data))
     }
-    pub(crate) fn expr(data: Box<dyn std::fmt::Display>) -> Self {
+    pub(crate) fn expr(data: Box<dyn ExprSample>) -> Self {
         Self(SampleData::Expr(data))
     }
 }
 
+impl Sample {
+    /// The sampled value without any formatting
+    ///
+    /// This is the [alternate](std::fmt#sign0) representation without ANSI
+    /// escape codes.
+    pub fn value(&self) -> String {
+        crate::ansi::strip(&format!("{self:#}"))
+    }
+
+    /// Kind of the sample
+    ///
+    /// One of `text`, `dice`, `die`, `interval`, `coin`, `color`,
+    /// `reference`, `template`, `tally` or `debug`.
+    pub fn kind(&self) -> &'static str {
+        match &self.0 {
+            SampleData::Text(_) => "text",
+            SampleData::Expr(e) => e.kind(),
+        }
+    }
+
+    /// Expression that generated the sample without any formatting
+    ///
+    /// Text samples have no expression.
+    pub fn expression(&self) -> Option<String> {
+        match &self.0 {
+            SampleData::Text(_) => None,
+            SampleData::Expr(e) => Some(crate::ansi::strip(&e.expression())),
+        }
+    }
+
+    /// Red, green and blue channels of a `color` sample
+    ///
+    /// Other kinds of samples have no channels.
+    pub fn rgb(&self) -> Option<(u8, u8, u8)> {
+        match &self.0 {
+            SampleData::Text(_) => None,
+            SampleData::Expr(e) => e.rgb(),
+        }
+    }
+
+    /// Numeric value of the sample, if it has one
+    ///
+    /// Dice, single dice from `split`, intervals and references to them have a
+    /// number. It doesn't
+    /// depend on the [`NumberFormat`], so it's the key to sort the results:
+    ///
+    /// ```
+    /// let mut output = rng_query::run_query("1d20 / 5 r")?;
+    /// output.sort_by(|a, b| a.number().unwrap().total_cmp(&b.number().unwrap()));
+    /// let values = output.iter().map(|s| s.number().unwrap()).collect::<Vec<_>>();
+    /// assert!(values.windows(2).all(|w| w[0] <= w[1]));
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn number(&self) -> Option<f64> {
+        match &self.0 {
+            SampleData::Text(_) => None,
+            SampleData::Expr(e) => e.number().map(|n| match n {
+                Num::Int(n) => n as f64,
+                Num::Float(n) => n.into(),
+            }),
+        }
+    }
+
+    /// Natural roll of a `dice` sample with a single die, like the `14` of
+    /// `1d20+5`
+    ///
+    /// Other samples, and rolls that ended with more dice, like an exploding
+    /// one, have no natural roll.
+    ///
+    /// ```
+    /// let output = rng_query::run_query("1d20+5")?;
+    /// let natural = output[0].natural().unwrap();
+    /// assert_eq!(output[0].number(), Some(f64::from(natural) + 5.0));
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn natural(&self) -> Option<u16> {
+        match &self.0 {
+            SampleData::Text(_) => None,
+            SampleData::Expr(e) => e.natural(),
+        }
+    }
+
+    /// Compares the sampled values ignoring formatting
+    ///
+    /// A colored `heads` is the same value as a plain `heads`.
+    pub fn same_value(&self, other: &Sample) -> bool {
+        self.value() == other.value()
+    }
+
+    /// Writes the [`Display`] representation to `w`
+    ///
+    /// Without `colors`, the ANSI escape codes are removed while writing. No
+    /// intermediate [`String`] is built.
+    ///
+    /// [`Display`]: std::fmt::Display
+    pub fn write_to<W: std::io::Write>(&self, w: &mut W, colors: bool) -> std::io::Result<()> {
+        use std::io::Write as _;
+        if colors {
+            write!(w, "{self}")
+        } else {
+            write!(crate::ansi::StripWriter::new(w), "{self}")
+        }
+    }
+
+    /// Detailed representation
+    ///
+    /// Some samples, like dice rolls, show a breakdown of how the value was
+    /// obtained in multiple lines. The others are the same as [`Display`].
+    ///
+    /// [`Display`]: std::fmt::Display
+    pub fn pretty(&self) -> impl std::fmt::Display + '_ {
+        Pretty(self)
+    }
+}
+
+/// The same sample in more than one result, like a bound expression
+impl ExprSample for Rc<Sample> {
+    fn kind(&self) -> &'static str {
+        Sample::kind(self)
+    }
+
+    fn expression(&self) -> String {
+        match &self.0 {
+            SampleData::Text(_) => String::new(),
+            SampleData::Expr(e) => e.expression(),
+        }
+    }
+
+    fn rgb(&self) -> Option<(u8, u8, u8)> {
+        Sample::rgb(self)
+    }
+
+    fn number(&self) -> Option<Num> {
+        match &self.0 {
+            SampleData::Text(_) => None,
+            SampleData::Expr(e) => e.number(),
+        }
+    }
+
+    fn natural(&self) -> Option<u16> {
+        Sample::natural(self)
+    }
+
+    fn split_dice(&self) -> Option<Vec<Sample>> {
+        match &self.0 {
+            SampleData::Text(_) => None,
+            SampleData::Expr(e) => e.split_dice(),
+        }
+    }
+
+    fn fmt_pretty(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&Pretty(self), f)
+    }
+}
+
+struct Pretty<'a>(&'a Sample);
+
+impl std::fmt::Display for Pretty<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 .0 {
+            SampleData::Text(t) => t.fmt(f),
+            SampleData::Expr(e) => e.fmt_pretty(f),
+        }
+    }
+}
+
 impl std::fmt::Display for Sample {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.0 {
@@ -58,94 +264,1045 @@ impl From<Vec<Sample>> for EvalRes {
     }
 }
 
+/// Evaluation context
+pub(crate) struct Ctx<'a> {
+    pub rng: &'a mut Generator,
+    /// Format of the numeric results
+    pub fmt: NumberFormat,
+    /// Only ASCII in the results of the expressions, see [`State::set_ascii`]
+    ///
+    /// [`State::set_ascii`]: crate::State::set_ascii
+    pub ascii: bool,
+    /// 24 bit color swatches, see [`State::set_truecolor`]
+    ///
+    /// [`State::set_truecolor`]: crate::State::set_truecolor
+    pub truecolor: bool,
+    /// Samples of the bound expressions in this query, by name
+    pub bindings: Rc<RefCell<HashMap<Rc<str>, Rc<Sample>>>>,
+    /// First error of the evaluation, see [`Ctx::fail`]
+    error: Rc<RefCell<Option<Error>>>,
+    trace: Option<&'a mut dyn FnMut(&str)>,
+}
+
+impl<'a> Ctx<'a> {
+    pub fn new(rng: &'a mut Generator, trace: Option<&'a mut dyn FnMut(&str)>) -> Self {
+        Self {
+            rng,
+            fmt: NumberFormat::default(),
+            ascii: false,
+            truecolor: true,
+            bindings: Default::default(),
+            error: Default::default(),
+            trace,
+        }
+    }
+
+    /// Same context with another generator
+    pub fn with_rng<'b>(&'b mut self, rng: &'b mut Generator) -> Ctx<'b> {
+        Ctx {
+            rng,
+            fmt: self.fmt,
+            ascii: self.ascii,
+            truecolor: self.truecolor,
+            bindings: Rc::clone(&self.bindings),
+            error: Rc::clone(&self.error),
+            trace: match &mut self.trace {
+                Some(trace) => Some(&mut **trace),
+                None => None,
+            },
+        }
+    }
+
+    /// Stops the query with an error
+    ///
+    /// The evaluation goes on, but the results are discarded and only the
+    /// first error is returned, see [`Ctx::take_error`].
+    pub fn fail(&mut self, err: Error) {
+        self.error.borrow_mut().get_or_insert(err);
+    }
+
+    /// The first error of the evaluation, if any
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.borrow_mut().take()
+    }
+
+    /// Report a step that used the RNG
+    ///
+    /// The message is only built if there is someone listening.
+    pub fn trace(&mut self, msg: impl FnOnce() -> String) {
+        if let Some(trace) = &mut self.trace {
+            trace(&msg());
+        }
+    }
+}
+
 pub(crate) trait Eval {
-    fn eval(&self, rng: &mut Pcg) -> EvalRes;
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes;
 }
 
 impl<T, R> Eval for T
 where
-    T: Fn(&mut Pcg) -> R,
+    T: Fn(&mut Ctx) -> R,
     R: Into<EvalRes>,
 {
-    fn eval(&self, rng: &mut Pcg) -> EvalRes {
-        (self)(rng).into()
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        (self)(ctx).into()
     }
 }
 
 impl Eval for Query {
-    fn eval(&self, rng: &mut Pcg) -> EvalRes {
-        self.root.eval(rng)
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        self.root.eval(ctx)
     }
 }
 
 impl Eval for Choose {
-    fn eval(&self, rng: &mut Pcg) -> EvalRes {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
         let Self { entries, options } = self;
 
-        let selected = select(rng, entries, options);
+        // drawn first, so it doesn't depend on the selection
+        let base_seed = options.independent.then(|| ctx.rng.next_u64());
+
+        let selected = match select(ctx, entries, options) {
+            Ok(selected) => selected,
+            Err(err) => {
+                ctx.fail(err);
+                return EvalRes::Emtpy;
+            }
+        };
+        let debug = options.debug.then(|| {
+            let mut ids = ids(&selected);
+            ids.sort_unstable();
+            Sample::expr(Box::new(DebugSample { ids }))
+        });
 
         if selected.is_empty() {
-            return EvalRes::Emtpy;
+            return debug.map_or(EvalRes::Emtpy, EvalRes::from);
         }
 
         let mut v = Vec::with_capacity(selected.len());
-        for (_, entry) in selected {
-            match entry.eval(rng) {
+        let mut draws = HashMap::<u64, u64>::new();
+        for ChooseEntry { entry, key, .. } in selected {
+            let res = match base_seed {
+                Some(base) => {
+                    // the same entry drawn again uses the next stream
+                    let n = draws.entry(key).or_default();
+                    let seed = mix_key(base ^ key, &n.to_le_bytes());
+                    *n += 1;
+                    let mut rng = Generator::new(ctx.rng.algorithm(), Some(seed));
+                    entry.eval(&mut ctx.with_rng(&mut rng))
+                }
+                None => entry.eval(ctx),
+            };
+            match res {
                 EvalRes::Emtpy => {}
                 EvalRes::Single(s) => v.push(s),
                 EvalRes::Many(mut vv) => v.append(&mut vv),
             }
         }
+        if options.split {
+            v = split_dice(v);
+        }
+        if options.unique {
+            let mut seen = std::collections::HashSet::new();
+            v.retain(|s| seen.insert(s.value()));
+        }
+        if options.tally {
+            v = tally(v);
+        }
+        if let Some(template) = &options.template {
+            let sample = TemplateSample {
+                template: Rc::clone(template),
+                samples: v,
+            };
+            v = vec![Sample::expr(Box::new(sample))];
+        }
+        v.extend(debug);
         EvalRes::Many(v)
     }
 }
 
+/// Replaces the dice rolls with a sample for each kept die
+fn split_dice(samples: Vec<Sample>) -> Vec<Sample> {
+    let mut out = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let dice = match &sample.0 {
+            SampleData::Expr(e) => e.split_dice(),
+            SampleData::Text(_) => None,
+        };
+        match dice {
+            Some(mut dice) => out.append(&mut dice),
+            None => out.push(sample),
+        }
+    }
+    out
+}
+
+/// Joins the samples with the same value, in order of first appearance
+fn tally(samples: Vec<Sample>) -> Vec<Sample> {
+    let mut tallies = Vec::<TallySample>::new();
+    let mut index = HashMap::<String, usize>::new();
+    for sample in samples {
+        let value = sample.value();
+        if let Some(&i) = index.get(&value) {
+            tallies[i].count += 1;
+        } else {
+            index.insert(value, tallies.len());
+            tallies.push(TallySample { sample, count: 1 });
+        }
+    }
+    tallies
+        .into_iter()
+        .map(|t| Sample::expr(Box::new(t)))
+        .collect()
+}
+
+/// A result and how many times it was repeated, from the `tally` option
+struct TallySample {
+    sample: Sample,
+    count: usize,
+}
+
+impl ExprSample for TallySample {
+    fn kind(&self) -> &'static str {
+        "tally"
+    }
+
+    fn expression(&self) -> String {
+        self.sample.expression().unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for TallySample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "{:#} x{}", self.sample, self.count)
+        } else {
+            write!(f, "{} {}", self.sample, format!("x{}", self.count).dimmed())
+        }
+    }
+}
+
+/// Ids of the selected entries, from the `debug` option
+///
+/// The ids are sorted, not in the order they were drawn.
+struct DebugSample {
+    ids: Vec<usize>,
+}
+
+impl ExprSample for DebugSample {
+    fn kind(&self) -> &'static str {
+        "debug"
+    }
+
+    fn expression(&self) -> String {
+        "debug".to_string()
+    }
+}
+
+impl std::fmt::Display for DebugSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ids = self
+            .ids
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if f.alternate() {
+            f.write_str(&ids)
+        } else {
+            write!(f, "{} [{ids}]", "selected:".dimmed())
+        }
+    }
+}
+
+/// All the results of a choose joined in a template
+struct TemplateSample {
+    template: Rc<str>,
+    samples: Vec<Sample>,
+}
+
+impl ExprSample for TemplateSample {
+    fn kind(&self) -> &'static str {
+        "template"
+    }
+
+    fn expression(&self) -> String {
+        self.template.to_string()
+    }
+}
+
+impl std::fmt::Display for TemplateSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut chars = self.template.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                write!(f, "{c}")?;
+            } else if chars.next_if_eq(&'%').is_some() {
+                f.write_str("%")?;
+            } else {
+                for (i, sample) in self.samples.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    if f.alternate() {
+                        write!(f, "{sample:#}")?;
+                    } else {
+                        write!(f, "{sample}")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Eval for Entry {
-    fn eval(&self, rng: &mut Pcg) -> EvalRes {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
         match self {
             Entry::Text(t) => Sample::text(t.clone()).into(),
-            Entry::Expr(e) => e.eval(rng),
+            Entry::Expr(e) => e.eval(ctx),
         }
     }
 }
 
 fn select(
-    rng: &mut Pcg,
-    entries: &[(usize, Entry)],
+    ctx: &mut Ctx,
+    entries: &[ChooseEntry],
     options: &ChooseOptions,
-) -> Vec<(usize, Entry)> {
+) -> Result<Vec<ChooseEntry>, Error> {
     if entries.is_empty() {
-        return vec![];
+        return Ok(vec![]);
     }
 
     let n = match options.amount {
         Amount::All => entries.len(),
         Amount::N(n) => n as usize,
+        Amount::Range(low, high) => {
+            let (mut low, mut high) = (low as usize, high as usize);
+            if !options.repeating {
+                // can't select more than there is
+                high = high.min(entries.len());
+                low = low.min(high);
+            }
+            let n = ctx.rng.gen_range(low..=high);
+            ctx.trace(|| format!("amount {low}-{high}: {n}"));
+            n
+        }
     };
 
     // optimization for all
+    //
+    // Nothing is randomly drawn here, so entries with weight 0 are also
+    // included.
     if !options.repeating && n >= entries.len() {
         let mut entries = entries.to_vec();
-        if !options.keep_order {
-            entries.shuffle(rng);
+        if !options.keep_order && entries.len() > 1 {
+            entries.shuffle(ctx.rng);
+            ctx.trace(|| format!("shuffle: {:?}", ids(&entries)));
         }
-        return entries;
+        return Ok(entries);
     }
 
     // general case
-    let mut selected = if options.repeating {
+    let weighted = entries
+        .iter()
+        .any(|e| e.weight != ChooseEntry::DEFAULT_WEIGHT);
+    let mut selected = if weighted {
+        select_weighted(ctx.rng, entries, n, options.repeating)?
+    } else if options.repeating {
         let mut selected = Vec::with_capacity(n);
         for _ in 0..n {
-            let entry = entries.choose(rng).unwrap();
+            let entry = entries.choose(ctx.rng).unwrap();
             selected.push(entry.clone());
         }
         selected
     } else {
-        entries.choose_multiple(rng, n).cloned().collect()
+        entries.choose_multiple(ctx.rng, n).cloned().collect()
     };
+    ctx.trace(|| format!("select: {:?}", ids(&selected)));
 
     if options.keep_order {
-        selected.sort_unstable_by_key(|e| e.0);
+        // stable, repeated entries stay in draw order
+        selected.sort_by_key(|e| e.id);
+    }
+    Ok(selected)
+}
+
+fn ids(entries: &[ChooseEntry]) -> Vec<usize> {
+    entries.iter().map(|e| e.id).collect()
+}
+
+fn select_weighted(
+    rng: &mut Generator,
+    entries: &[ChooseEntry],
+    n: usize,
+    repeating: bool,
+) -> Result<Vec<ChooseEntry>, Error> {
+    // entries with weight 0 can never be drawn
+    let candidates = entries
+        .iter()
+        .filter(|e| e.weight > 0.0)
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // each weight is finite, but the sum may not be
+    if !candidates.iter().map(|e| e.weight).sum::<f64>().is_finite() {
+        return Err(Error::ParseQuery(
+            "the sum of the weights is too big".to_string(),
+        ));
+    }
+    let bad_weights = |e| Error::ParseQuery(format!("bad weights: {e}"));
+    if repeating {
+        let mut selected = Vec::with_capacity(n);
+        for _ in 0..n {
+            let entry = candidates
+                .choose_weighted(rng, |e| e.weight)
+                .map_err(bad_weights)?;
+            selected.push((*entry).clone());
+        }
+        Ok(selected)
+    } else {
+        let selected = candidates
+            .choose_multiple_weighted(rng, n, |e| e.weight)
+            .map_err(bad_weights)?
+            .map(|e| (*e).clone())
+            .collect();
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+    use owo_colors::OwoColorize;
+
+    fn run(seed: u64, query: &str) -> Vec<String> {
+        let mut state = State::with_seed(seed);
+        let samples = state.run_query(query).expect("query failed");
+        samples.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn kind_and_expression() {
+        let mut state = State::with_seed(0);
+        let samples = state.run_query("a, 2d6+1, [1..3], coin / all o").unwrap();
+        let kinds = samples.iter().map(Sample::kind).collect::<Vec<_>>();
+        assert_eq!(kinds, ["text", "dice", "interval", "coin"]);
+        let exprs = samples.iter().map(Sample::expression).collect::<Vec<_>>();
+        assert_eq!(exprs[0], None);
+        assert_eq!(exprs[1].as_deref(), Some("2d6+1"));
+        assert_eq!(exprs[2].as_deref(), Some("[1..3]"));
+        assert_eq!(exprs[3].as_deref(), Some("coin"));
+    }
+
+    #[test]
+    fn number() {
+        let mut state = State::with_seed(0);
+        state.set_number_format(
+            NumberFormat::default()
+                .with_grouping(Some('.'))
+                .with_decimal(','),
+        );
+        let samples = state
+            .run_query("a, 1000d1, 3d1 div 2, [1..1], coin / all o")
+            .unwrap();
+        let numbers = samples.iter().map(Sample::number).collect::<Vec<_>>();
+        assert_eq!(numbers, [None, Some(1000.0), Some(1.5), Some(1.0), None]);
+        assert_eq!(samples[1].value(), "1.000");
+
+        // sorting seeded rolls by their number
+        let mut samples = state.run_query("1d20 / 20 r").unwrap();
+        samples.sort_by(|a, b| a.number().unwrap().total_cmp(&b.number().unwrap()));
+        let values = samples
+            .iter()
+            .map(|s| s.value().parse::<u32>().unwrap())
+            .collect::<Vec<_>>();
+        assert!(values.windows(2).all(|w| w[0] <= w[1]), "{values:?}");
+    }
+
+    #[test]
+    fn natural() {
+        for seed in 0..20 {
+            let mut state = State::with_seed(seed);
+            let samples = state
+                .run_query("1d20+5 as atk, atk, 2d20, 1d20!x2, [1..20] / all o")
+                .unwrap();
+            let natural = samples[0].natural().unwrap();
+            assert!((1..=20).contains(&natural), "{natural}");
+            assert_eq!(samples[0].number(), Some(f64::from(natural) + 5.0));
+            // only the dice sample itself
+            assert_eq!(samples[1].natural(), None);
+            assert_eq!(samples[2].natural(), None);
+            // without exploding
+            let exploded = samples[3].number().unwrap() > 19.0;
+            assert_eq!(samples[3].natural().is_none(), exploded);
+            assert_eq!(samples[4].natural(), None);
+        }
+    }
+
+    #[test]
+    fn same_value_ignores_ansi() {
+        let colored = Sample::text("heads".green().bold().to_string().into());
+        let plain = Sample::text("heads".into());
+        assert_ne!(colored.to_string(), plain.to_string());
+        assert!(colored.same_value(&plain));
+        assert!(!colored.same_value(&Sample::text("tails".into())));
+    }
+
+    #[test]
+    fn keep_order_repeating() {
+        for seed in 0..20 {
+            let mut state = State::with_seed(seed);
+            let samples = state.run_query("a, 1d6, b, [1..3] / 12 r o").unwrap();
+            assert_eq!(samples.len(), 12);
+            let rank = |s: &Sample| match (s.kind(), s.value().as_str()) {
+                ("text", "a") => 0,
+                ("dice", _) => 1,
+                ("text", "b") => 2,
+                ("interval", _) => 3,
+                other => panic!("unexpected {other:?}"),
+            };
+            let ranks = samples.iter().map(rank).collect::<Vec<_>>();
+            assert!(ranks.windows(2).all(|w| w[0] <= w[1]), "{ranks:?}");
+        }
+    }
+
+    #[test]
+    fn unique_results() {
+        for seed in 0..20 {
+            let res = run(seed, "coin / 10 r u");
+            assert!(!res.is_empty() && res.len() <= 2, "{res:?}");
+        }
+        assert_eq!(run(0, "a, b, a, 'a' / all o u"), ["a", "b"]);
+    }
+
+    #[test]
+    fn trace_dice_roll() {
+        let mut state = State::with_seed(42);
+        let mut trace = Vec::new();
+        let res = state
+            .run_query_traced("2d6", |msg| trace.push(msg.to_string()))
+            .unwrap();
+        assert_eq!(trace.len(), 2);
+        let total = trace
+            .iter()
+            .map(|t| t.strip_prefix("d6: ").unwrap().parse::<i32>().unwrap())
+            .sum::<i32>();
+        assert_eq!(format!("{:#}", res[0]), total.to_string());
+
+        // same seed, same draws
+        let mut again = Vec::new();
+        State::with_seed(42)
+            .run_query_traced("2d6", |msg| again.push(msg.to_string()))
+            .unwrap();
+        assert_eq!(trace, again);
+    }
+
+    #[test]
+    fn amount_range() {
+        for seed in 0..50 {
+            let res = run(seed, "a, b, c, d, e, f / 2-4");
+            assert!((2..=4).contains(&res.len()), "{res:?}");
+            let res = run(seed, "a, b, c / 2 - 10");
+            assert!((2..=3).contains(&res.len()), "{res:?}");
+            let res = run(seed, "a, b / 5-6 r");
+            assert!((5..=6).contains(&res.len()), "{res:?}");
+        }
+        assert!(State::new().run_query("a, b / 4-2").is_err());
+    }
+
+    #[test]
+    fn list_uses_no_rng() {
+        for list in ["/ all o", "/ list", "/ 5 o"] {
+            let mut state = State::with_seed(7);
+            for entry in ["a", "b", "c", "d"] {
+                state.add_data(entry);
+            }
+            let listed = state.run_query(list).unwrap();
+            assert_eq!(listed.len(), 4);
+            let after = state.run_query("1d20").unwrap()[0].to_string();
+
+            let fresh = run(7, "1d20");
+            assert_eq!([after], fresh.as_slice(), "{list}");
+        }
+    }
+
+    #[test]
+    fn template() {
+        assert_eq!(
+            run(0, r#"a, b, c / all o fmt "You got: %""#),
+            ["You got: a, b, c"]
+        );
+        assert_eq!(run(0, r#"a / fmt "%!""#), ["a!"]);
+        assert_eq!(
+            run(0, r#"50 / fmt "% is 50%% of 100""#),
+            ["50 is 50% of 100"]
+        );
+        assert_eq!(run(0, r#"a / fmt "say \"%\"""#), [r#"say "a""#]);
+        assert_eq!(
+            run(0, r#"a, {b, c / list fmt "<%>"} / list"#),
+            ["a", "<b, c>"]
+        );
+        // braces inside the template
+        assert_eq!(run(0, r#"a / fmt "{%}""#), ["{a}"]);
+        assert_eq!(
+            run(0, r#"{b / fmt "{% \"}\"}"}, c / list"#),
+            [r#"{b "}"}"#, "c"]
+        );
+    }
+
+    #[test]
+    fn repeating_more_than_entries() {
+        // not capped by the shuffle of all the entries
+        let mut seen_orders = std::collections::HashSet::new();
+        for seed in 0..50 {
+            let mut state = State::with_seed(seed);
+            state.add_data("a");
+            state.add_data("b");
+            let res = state.run_query("/3 r").unwrap();
+            let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+            assert_eq!(values.len(), 3);
+            assert!(values.iter().all(|v| v == "a" || v == "b"), "{values:?}");
+            seen_orders.insert(values);
+        }
+        // with replacement, all the 8 combinations can appear
+        assert_eq!(seen_orders.len(), 8);
+    }
+
+    #[test]
+    fn zero_weight_never_drawn() {
+        for seed in 0..50 {
+            let res = run(seed, "a, b *0, c / 5 r");
+            assert_eq!(res.len(), 5);
+            assert!(res.iter().all(|s| s != "b"), "{res:?}");
+        }
+    }
+
+    #[test]
+    fn zero_weight_listed_in_all() {
+        for seed in 0..10 {
+            let res = run(seed, "a, b *0, c / all");
+            assert_eq!(res.len(), 3);
+            assert!(res.iter().any(|s| s == "b"), "{res:?}");
+        }
+        assert_eq!(run(0, "a, b *0, c / list"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn weights_too_big() {
+        let nines = "9".repeat(400);
+        for options in ["3 r", "1"] {
+            let query = format!("a *{nines}, b / {options}");
+            let res = State::with_seed(0).run_query(&query);
+            assert!(matches!(res, Err(Error::ParseQuery(_))), "{options}");
+
+            // each weight is finite, but not the sum
+            let mut state = State::with_seed(0);
+            state.add_weighted_data("a", f64::MAX);
+            state.add_weighted_data("b", f64::MAX);
+            let res = state.run_query(&format!("c / {options}"));
+            assert!(matches!(res, Err(Error::ParseQuery(_))), "{options}");
+        }
+    }
+
+    #[test]
+    fn weighted_subquery() {
+        for seed in 0..50 {
+            let res = run(seed, "a *0, {b, c} *2 / 1");
+            assert!(res == ["b"] || res == ["c"], "{res:?}");
+        }
+    }
+
+    #[test]
+    fn subquery_is_one_entry() {
+        // each top level entry is equally likely, no matter the size of the
+        // subquery
+        let count = |query: &str| {
+            let mut state = State::with_seed(0);
+            (0..4000)
+                .filter(|_| state.run_query(query).unwrap()[0].value() == "a")
+                .count()
+        };
+        let nested = count("a, {b, c, d, e, f, g, h}");
+        assert!((1800..2200).contains(&nested), "{nested}");
+        // the same leafs flattened
+        let flat = count("a, b, c, d, e, f, g, h");
+        assert!((400..600).contains(&flat), "{flat}");
+    }
+
+    #[test]
+    fn nested_weighted_tables() {
+        // top level 3:1, then 3:1 and 1:1 inside
+        let query = "{goblin *3, orc} *3, {dragon, lich / 1} *1";
+        let mut state = State::with_seed(0);
+        let mut counts = HashMap::<String, usize>::new();
+        for _ in 0..8000 {
+            let res = state.run_query(query).unwrap();
+            assert_eq!(res.len(), 1);
+            *counts.entry(res[0].value()).or_default() += 1;
+        }
+        let expected = [
+            ("goblin", 4500), // 3/4 * 3/4
+            ("orc", 1500),    // 3/4 * 1/4
+            ("dragon", 1000), // 1/4 * 1/2
+            ("lich", 1000),
+        ];
+        assert_eq!(counts.len(), expected.len(), "{counts:?}");
+        for (leaf, n) in expected {
+            let got = counts[leaf];
+            assert!(got.abs_diff(n) < 150, "{leaf}: {got} of 8000, expected {n}");
+        }
+
+        // only the leaves are in the output
+        let res = run(0, "{a *2, b / 2} *3, {c, d / list} / all");
+        assert_eq!(res.len(), 4);
+        assert!(res.iter().all(|s| s.len() == 1), "{res:?}");
+    }
+
+    #[test]
+    fn shared_entries() {
+        // the same result with shared or separately parsed entries
+        for seed in 0..20 {
+            let shared = run(seed, "1d6, 1d6, [1..10], [1..10], 1d6 / all r");
+            let separate = run(seed, "1d6, 1 d6, [1..10], [1 .. 10], 1d6  / all r");
+            assert_eq!(shared, separate);
+        }
+    }
+
+    #[test]
+    fn either() {
+        let mut firsts = 0usize;
+        for seed in 0..1000 {
+            let values = run(seed, "either {a, b / all o} {c, d / all o}");
+            match values.as_slice() {
+                [a, b] if a == "a" && b == "b" => firsts += 1,
+                [c, d] if c == "c" && d == "d" => {}
+                _ => panic!("not a single branch: {values:?}"),
+            }
+        }
+        assert!(firsts.abs_diff(500) < 60, "first branch {firsts} of 1000");
+    }
+
+    #[test]
+    fn ascii() {
+        let mut state = State::with_seed(0);
+        state.set_ascii(true);
+        for _ in 0..20 {
+            let res = state
+                .run_query("coin, color, 2d6+1, [1..10], {1, 2.5} / all o")
+                .unwrap();
+            for sample in &res {
+                assert!(sample.to_string().is_ascii(), "{sample}");
+                assert!(sample.pretty().to_string().is_ascii(), "{sample}");
+            }
+            assert!(["H", "T"].contains(&res[0].value().as_str()));
+            let color = res[1].to_string();
+            assert!(color.starts_with('#') && color.len() == 7, "{color}");
+        }
+    }
+
+    #[test]
+    fn num_set() {
+        for seed in 0..50 {
+            let mut state = State::with_seed(seed);
+            let res = state.run_query("{1, 3, 5}, {-1, 0.5} / all o").unwrap();
+            assert_eq!(res[0].kind(), "interval");
+            assert_eq!(res[0].expression().as_deref(), Some("{1, 3, 5}"));
+            assert!(["1", "3", "5"].contains(&res[0].value().as_str()));
+            assert!(["-1", "0.5"].contains(&res[1].value().as_str()));
+        }
+
+        // not only numbers, or with options, is a subquery
+        let mut state = State::with_seed(0);
+        for query in [
+            "{1, a}",
+            "{1, 3 / 1}",
+            "{1, 3*2}",
+            "{1, 3} / list",
+            "{007, 010, +3}",
+            "{1, 2.0}",
+        ] {
+            let res = state.run_query(query).unwrap();
+            assert!(res.iter().all(|s| s.kind() == "text"), "{query}");
+        }
+    }
+
+    #[test]
+    fn references() {
+        let values = |seed, query| {
+            let mut state = State::with_seed(seed);
+            let res = state.run_query(query).unwrap();
+            res.iter()
+                .map(|s| s.value().parse::<i64>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        for seed in 0..50 {
+            let v = values(seed, "1d20 as atk, atk+5, atk, atk - 2 / all o");
+            assert_eq!(v[1], v[0] + 5);
+            assert_eq!(v[2], v[0]);
+            assert_eq!(v[3], v[0] - 2);
+
+            // the reference is before the named expression
+            let v = values(seed, "atk+5, 1d20 as atk / all o");
+            assert_eq!(v[0], v[1] + 5);
+
+            // the same value in other subqueries
+            let v = values(seed, "{1d20 as atk}, {atk, atk+1 / all o} / all o");
+            assert_eq!(v[1], v[0]);
+            assert_eq!(v[2], v[0] + 1);
+
+            // the named expression is not selected
+            let v = values(seed, "{1d20 as atk / 0}, atk+5 / all o");
+            assert!((6..=25).contains(&v[0]), "{v:?}");
+        }
+
+        // the number is added, not the formatted value
+        let mut state = State::with_seed(0);
+        state.set_number_format(
+            NumberFormat::default()
+                .with_grouping(Some(','))
+                .with_decimal(','),
+        );
+        let res = state.run_query("1000d1 as x, x+5 / all o").unwrap();
+        assert_eq!(res[1].value(), "1,005");
+        assert_eq!(res[1].number(), Some(1005.0));
+        let res = state.run_query("7d1 div 4 as y, y+1 / all o").unwrap();
+        assert_eq!(res[1].value(), "2,75");
+
+        // each query rolls again
+        let mut state = State::with_seed(0);
+        let rolls = (0..20)
+            .map(|_| state.run_query("1d1000 as x, x / all o").unwrap()[1].value())
+            .collect::<std::collections::HashSet<_>>();
+        assert!(rolls.len() > 1);
+    }
+
+    #[test]
+    fn independent_streams() {
+        let results = |query: &str| {
+            let mut state = State::with_seed(7);
+            let res = state.run_query(query).unwrap();
+            let mut results = res
+                .iter()
+                .map(|s| (s.expression().unwrap(), s.value()))
+                .collect::<Vec<_>>();
+            results.sort();
+            results
+        };
+        let entries = ["1d20", "3d6!", "[1..1000]", "coin", "{d4, d8 / 2}"];
+        let forward = results(&format!("{} / all i", entries.join(", ")));
+        let mut reversed = entries;
+        reversed.reverse();
+        let backward = results(&format!("{} / all o i", reversed.join(", ")));
+        assert_eq!(forward.len(), 6);
+        assert_eq!(forward, backward);
+
+        // other entries don't matter either
+        let some = results(&format!("{}, 2d10 / all i", entries[..2].join(", ")));
+        let shared = some.iter().filter(|r| r.0 != "2d10").collect::<Vec<_>>();
+        assert_eq!(shared.len(), 2);
+        assert!(shared.iter().all(|r| forward.contains(r)), "{some:?}");
+    }
+
+    #[test]
+    fn combine_stacks() {
+        let stack = |entries: &[&str]| {
+            let mut state = State::with_seed(0);
+            for e in entries {
+                state.add_entry(e).unwrap();
+            }
+            state
+        };
+        let list = |mut state: State| {
+            let res = state.run_query("/ all o").unwrap();
+            res.iter()
+                .map(|s| s.expression().unwrap_or_else(|| s.value()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut a = stack(&["x", "y", "d6"]);
+        a.union_stack(&stack(&["z", "y", "z", "d6"]));
+        assert_eq!(list(a), ["x", "y", "d6", "z", "d6"]);
+
+        let mut a = stack(&["x", "y", "d6", "x"]);
+        a.intersect_stack(&stack(&["x", "d6"]));
+        assert_eq!(list(a), ["x", "x"]);
+
+        // the new entries are still after the stack
+        let mut a = stack(&["x"]);
+        a.union_stack(&stack(&["y"]));
+        let res = a.run_query("z / list").unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(values, ["x", "y", "z"]);
+    }
+
+    #[test]
+    fn write_to() {
+        let res = State::with_seed(0)
+            .run_query("3d6+1, coin / all o")
+            .unwrap();
+        let mut out = Vec::new();
+        for sample in &res {
+            sample.write_to(&mut out, false).unwrap();
+            out.push(b'\n');
+        }
+        let text = String::from_utf8(out).unwrap();
+        let expected = res
+            .iter()
+            .map(|s| format!("{}\n", crate::ansi::strip(&s.to_string())))
+            .collect::<String>();
+        assert_eq!(text, expected);
+        assert!(!text.contains('\x1b'));
+        assert!(text.starts_with("3d6+1: ["), "{text}");
+
+        let mut out = Vec::new();
+        res[0].write_to(&mut out, true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), res[0].to_string());
+        assert!(res[0].to_string().contains('\x1b'));
+    }
+
+    #[test]
+    fn debug_ids() {
+        let mut state = State::with_seed(0);
+        for e in ["a", "b", "c", "d", "e"] {
+            state.add_data(e);
+        }
+        let res = state.run_query("/3 debug").unwrap();
+        assert_eq!(res.len(), 4);
+        let debug = &res[3];
+        assert_eq!(debug.kind(), "debug");
+        let ids = debug
+            .value()
+            .split(", ")
+            .map(|id| id.parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids.iter().all(|&id| id < 5));
+        for sample in &res[..3] {
+            let id = sample.value().as_bytes()[0] - b'a';
+            assert!(ids.contains(&(id as usize)));
+        }
+
+        let res = state.run_query("x, y / debug").unwrap();
+        assert_eq!(res.len(), 2);
+        // the stack is used, so nothing is selected
+        let res = state.run_query("/ debug").unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].value(), "");
+    }
+
+    #[test]
+    fn untrimmed_entries() {
+        let mut state = State::with_seed(0);
+        state.set_trim_entries(false);
+        state.add_data("  ascii art  ");
+        state.add_data("\t😀 tab");
+        state.add_entry(" 1d1 ").unwrap();
+        let res = state.run_query(" a ,b,  {c } / list").unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(values, ["  ascii art  ", "\t😀 tab", "1", " a ", "b", "c "]);
+        assert_eq!(res[2].kind(), "dice");
+
+        state.set_trim_entries(true);
+        state.add_data("  ascii art  ");
+        let res = state.run_query(" a ,b / list").unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(values, ["ascii art", "a", "b"]);
+    }
+
+    #[test]
+    fn split_dice() {
+        for seed in 0..20 {
+            let total = State::with_seed(seed).run_query("4d6k3+1").unwrap()[0].value();
+            let res = State::with_seed(seed).run_query("4d6k3+1 / split").unwrap();
+            assert_eq!(res.len(), 3);
+            assert!(res.iter().all(|s| s.kind() == "die"));
+            assert!(res.iter().all(|s| s.expression().as_deref() == Some("d6")));
+            let sum = res
+                .iter()
+                .map(|s| s.value().parse::<i32>().unwrap())
+                .sum::<i32>();
+            assert_eq!((sum + 1).to_string(), total);
+        }
+
+        // other samples are kept
+        let res = State::with_seed(0)
+            .run_query("a, 2d4, 3d1 / all o split")
+            .unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(values.len(), 1 + 2 + 3);
+        assert_eq!(values[0], "a");
+        assert_eq!(values[3..], ["1", "1", "1"]);
+        assert_eq!(res[3].to_string(), "d1: 1");
+    }
+
+    #[test]
+    fn tally_counts() {
+        let mut state = State::with_seed(0);
+        let res = state.run_query("a, b, c / 100 r tally").unwrap();
+        assert!(res.len() <= 3);
+        let mut total = 0;
+        let mut values = Vec::new();
+        for sample in &res {
+            assert_eq!(sample.kind(), "tally");
+            let value = sample.value();
+            let (value, count) = value.rsplit_once(" x").unwrap();
+            values.push(value.to_string());
+            total += count.parse::<usize>().unwrap();
+        }
+        assert_eq!(total, 100);
+        values.sort();
+        values.dedup();
+        assert_eq!(values.len(), res.len());
+
+        // colors are ignored
+        let res = state
+            .run_query("coin, heads, 1d1, 1 / all o tally")
+            .unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert!(values == ["heads x2", "1 x2"] || values == ["tails x1", "heads x1", "1 x2"]);
+    }
+
+    #[test]
+    fn custom_separators() {
+        use crate::Separators;
+
+        let sep = |entry: &str, options| Separators {
+            entry: entry.to_string(),
+            options,
+        };
+        let mut state = State::with_seed_and_separators(0, sep("|", ';')).unwrap();
+        let res = state.run_query("a, b | c / d | 1d6 ; all o").unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(values[..2], ["a, b", "c / d"]);
+        assert!(res[2].expression().is_some());
+
+        assert!(State::with_separators(sep(";", ';')).is_err());
+        assert!(State::with_separators(sep("{", '/')).is_err());
+        assert!(State::with_separators(sep(",", ' ')).is_err());
+        assert!(State::with_separators(sep("", '/')).is_ok());
+    }
+
+    #[test]
+    fn expand() {
+        let mut state = State::with_seed(0);
+        for _ in 0..100 {
+            let res = state.run_query("expand 1..5 /2").unwrap();
+            let values = res
+                .iter()
+                .map(|s| s.value().parse::<i64>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(values.len(), 2);
+            assert_ne!(values[0], values[1]);
+            assert!(values.iter().all(|v| (1..5).contains(v)), "{values:?}");
+        }
+        let res = state.run_query("a, expand -1..=1, b / list").unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert_eq!(values, ["a", "-1", "0", "1", "b"]);
     }
-    selected
 }
@@ -2,16 +2,17 @@ use std::rc::Rc;
 
 use crate::{eval::Eval, Error};
 
+pub(crate) mod binding;
+mod charclass;
 mod coin;
 mod color;
-mod dice;
-mod interval;
+pub(crate) mod dice;
+pub(crate) mod interval;
 mod uuid;
 
 pub fn parse_expr(expr: &str) -> Result<Option<Rc<dyn Eval>>, Error> {
     // one word specials
     let thing: Option<Rc<dyn Eval>> = match expr {
-        "coin" => Some(Rc::new(coin::toss_coin)),
         "color" => Some(Rc::new(color::gen_color)),
         "uuid" => Some(Rc::new(uuid::gen_uuid)),
         _ => None,
@@ -21,6 +22,18 @@ pub fn parse_expr(expr: &str) -> Result<Option<Rc<dyn Eval>>, Error> {
     }
 
     // more complex ones, maybe add a precheck match in the future
+    match expr.parse::<coin::Coin>() {
+        Err(coin::CoinParseError::NoMatch) => {}
+        Ok(c) => return Ok(Some(Rc::new(c))),
+        Err(e) => return Err(Error::Expr(e.to_string())),
+    }
+
+    match expr.parse::<charclass::CharClass>() {
+        Err(charclass::CharClassParseError::NoMatch) => {}
+        Ok(c) => return Ok(Some(Rc::new(c))),
+        Err(e) => return Err(Error::Expr(e.to_string())),
+    }
+
     match expr.parse::<dice::Roll>() {
         Err(dice::RollParseError::NoMatch) => {}
         Ok(r) => return Ok(Some(Rc::new(r))),
@@ -33,5 +46,11 @@ pub fn parse_expr(expr: &str) -> Result<Option<Rc<dyn Eval>>, Error> {
         Err(e) => return Err(Error::Expr(e.to_string())),
     }
 
+    match expr.parse::<interval::DynInterval>() {
+        Err(interval::IntervalParseError::NoMatch) => {}
+        Ok(i) => return Ok(Some(Rc::new(i))),
+        Err(e) => return Err(Error::Expr(e.to_string())),
+    }
+
     Ok(None)
 }
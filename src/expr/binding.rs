@@ -0,0 +1,119 @@
+//! Named expressions, like `1d20 as atk`, and references to them, like `atk+5`
+
+use std::rc::Rc;
+
+use crate::{
+    eval::{Ctx, Eval, EvalRes, ExprSample},
+    expr::interval::Num,
+    Sample,
+};
+
+/// An expression with a name
+///
+/// It's evaluated once per query, the first time it or a [`Reference`] to it
+/// is evaluated. After that, it always gives the same sample.
+pub(crate) struct Binding {
+    name: Rc<str>,
+    expr: Rc<dyn Eval>,
+}
+
+impl Binding {
+    pub fn new(name: &str, expr: Rc<dyn Eval>) -> Self {
+        Self {
+            name: name.into(),
+            expr,
+        }
+    }
+
+    fn resolve(&self, ctx: &mut Ctx) -> Rc<Sample> {
+        if let Some(sample) = ctx.bindings.borrow().get(&self.name) {
+            return Rc::clone(sample);
+        }
+        let sample = match self.expr.eval(ctx) {
+            EvalRes::Single(sample) => Rc::new(sample),
+            _ => unreachable!("expressions give a single sample"),
+        };
+        ctx.bindings
+            .borrow_mut()
+            .insert(Rc::clone(&self.name), Rc::clone(&sample));
+        sample
+    }
+}
+
+impl Eval for Binding {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        Sample::expr(Box::new(self.resolve(ctx))).into()
+    }
+}
+
+/// The value of a [`Binding`] plus a modifier
+pub(crate) struct Reference {
+    binding: Rc<Binding>,
+    modifier: i64,
+}
+
+impl Reference {
+    pub fn new(binding: Rc<Binding>, modifier: i64) -> Self {
+        Self { binding, modifier }
+    }
+}
+
+impl Eval for Reference {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        let bound = self.binding.resolve(ctx);
+        // add to the number, the formatted value may have grouping
+        let number = bound.number().map(|n| match n {
+            Num::Int(n) => Num::Int(n.saturating_add(self.modifier)),
+            Num::Float(n) => Num::Float(n + self.modifier as f32),
+        });
+        let value = match number {
+            _ if self.modifier == 0 => bound.value(),
+            Some(Num::Int(n)) => ctx.fmt.int(n),
+            Some(Num::Float(n)) => ctx.fmt.float(n),
+            // not a number, like `heads`
+            None => format!("{}{:+}", bound.value(), self.modifier),
+        };
+        let sample = ReferenceSample {
+            name: Rc::clone(&self.binding.name),
+            modifier: self.modifier,
+            number,
+            value,
+        };
+        Sample::expr(Box::new(sample)).into()
+    }
+}
+
+struct ReferenceSample {
+    name: Rc<str>,
+    modifier: i64,
+    number: Option<Num>,
+    value: String,
+}
+
+impl ExprSample for ReferenceSample {
+    fn kind(&self) -> &'static str {
+        "reference"
+    }
+
+    fn expression(&self) -> String {
+        if self.modifier == 0 {
+            self.name.to_string()
+        } else {
+            format!("{}{:+}", self.name, self.modifier)
+        }
+    }
+
+    fn number(&self) -> Option<Num> {
+        self.number
+    }
+}
+
+impl std::fmt::Display for ReferenceSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            f.write_str(&self.value)
+        } else {
+            write!(f, "{}: {}", self.expression(), self.value)
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Random char from a named class
+
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::{
+    eval::{Ctx, Eval, EvalRes},
+    regex, Sample,
+};
+
+/// Named classes of chars, as inclusive ranges
+///
+/// Kept small on purpose, these are not full Unicode categories.
+const CLASSES: &[(&str, &[(char, char)])] = &[
+    ("digit", &[('0', '9')]),
+    ("lower", &[('a', 'z')]),
+    ("upper", &[('A', 'Z')]),
+    ("letter", &[('A', 'Z'), ('a', 'z')]),
+    ("alnum", &[('0', '9'), ('A', 'Z'), ('a', 'z')]),
+    ("hex", &[('0', '9'), ('a', 'f')]),
+    ("punct", &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')]),
+    // α to ω
+    ("greek", &[('\u{3B1}', '\u{3C9}')]),
+    // emoticons block
+    ("emoji", &[('\u{1F600}', '\u{1F64F}')]),
+];
+
+/// A char from a class, like `charclass(digit)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharClass {
+    ranges: &'static [(char, char)],
+}
+
+/// Error from [`CharClass::from_str`]
+#[derive(Debug)]
+pub enum CharClassParseError {
+    NoMatch,
+    Invalid(String),
+}
+
+impl std::fmt::Display for CharClassParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CharClassParseError::NoMatch => f.write_str("the input is not a char class"),
+            CharClassParseError::Invalid(e) => write!(f, "invalid char class: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CharClassParseError {}
+
+impl FromStr for CharClass {
+    type Err = CharClassParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = regex!(r"\Acharclass\(\s*(\w+)\s*\)\z");
+
+        let caps = re.captures(s).ok_or(CharClassParseError::NoMatch)?;
+        let name = &caps[1];
+        let Some(&(_, ranges)) = CLASSES.iter().find(|(n, _)| *n == name) else {
+            let names = CLASSES.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+            return Err(CharClassParseError::Invalid(format!(
+                "unknown class {name:?}, expected one of: {}",
+                names.join(", ")
+            )));
+        };
+        Ok(CharClass { ranges })
+    }
+}
+
+impl Eval for CharClass {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        let len = |&(start, end): &(char, char)| end as u32 - start as u32 + 1;
+        let total = self.ranges.iter().map(len).sum::<u32>();
+        let mut i = ctx.rng.gen_range(0..total);
+        let mut c = '\0';
+        for range in self.ranges {
+            if i < len(range) {
+                // the ranges don't have surrogates
+                c = char::from_u32(range.0 as u32 + i).expect("valid char");
+                break;
+            }
+            i -= len(range);
+        }
+        ctx.trace(|| format!("charclass: {c}"));
+        Sample::text(c.to_string().into()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Algorithm, Generator};
+
+    fn sample(s: &str, seed: u64) -> char {
+        let class = s.parse::<CharClass>().expect("failed to parse");
+        let mut rng = Generator::new(Algorithm::Pcg, Some(seed));
+        let EvalRes::Single(sample) = class.eval(&mut Ctx::new(&mut rng, None)) else {
+            panic!("not a single sample")
+        };
+        let value = sample.value();
+        let mut chars = value.chars();
+        let c = chars.next().unwrap();
+        assert!(chars.next().is_none());
+        c
+    }
+
+    #[test]
+    fn digit() {
+        let mut seen = std::collections::HashSet::new();
+        for seed in 0..200 {
+            let c = sample("charclass(digit)", seed);
+            assert!(c.is_ascii_digit(), "{c}");
+            seen.insert(c);
+        }
+        assert_eq!(seen.len(), 10);
+    }
+
+    #[test]
+    fn all_classes() {
+        for &(name, ranges) in CLASSES {
+            for seed in 0..50 {
+                let c = sample(&format!("charclass( {name} )"), seed);
+                assert!(ranges.iter().any(|&(s, e)| (s..=e).contains(&c)), "{c}");
+            }
+        }
+        for seed in 0..50 {
+            assert!(sample("charclass(alnum)", seed).is_ascii_alphanumeric());
+        }
+    }
+
+    #[test]
+    fn unknown() {
+        assert!(matches!(
+            "charclass(nope)".parse::<CharClass>(),
+            Err(CharClassParseError::Invalid(_))
+        ));
+        assert!(matches!(
+            "charclass digit".parse::<CharClass>(),
+            Err(CharClassParseError::NoMatch)
+        ));
+    }
+}
@@ -1,16 +1,194 @@
 //! Coin expression
 
+use std::{fmt::Display, rc::Rc, str::FromStr};
+
 use owo_colors::OwoColorize;
 use rand::Rng;
 
-use crate::{Pcg, Sample};
+use crate::{
+    eval::{Ctx, Eval, EvalRes, ExprSample},
+    regex, Sample,
+};
+
+/// A coin toss
+///
+/// Each side can be mapped to a different value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coin {
+    heads: Option<Rc<str>>,
+    tails: Option<Rc<str>>,
+}
+
+/// Error from [`Coin::from_str`]
+#[derive(Debug)]
+pub enum CoinParseError {
+    NoMatch,
+    Invalid(String),
+}
+
+impl std::fmt::Display for CoinParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoinParseError::NoMatch => f.write_str("the input is not a coin"),
+            CoinParseError::Invalid(e) => write!(f, "invalid coin: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CoinParseError {}
+
+impl FromStr for Coin {
+    type Err = CoinParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = regex!(r"\Acoin((?:\s+\w+=\S+)*)\z");
+
+        let caps = re.captures(s).ok_or(CoinParseError::NoMatch)?;
+
+        let mut coin = Coin::default();
+        for mapping in caps[1].split_whitespace() {
+            let (side, value) = mapping.split_once('=').unwrap(); // checked by regex
+            let slot = match side {
+                "heads" => &mut coin.heads,
+                "tails" => &mut coin.tails,
+                _ => return Err(CoinParseError::Invalid(format!("unknown side: {side:?}"))),
+            };
+            if slot.is_some() {
+                return Err(CoinParseError::Invalid(format!("duplicate side: {side}")));
+            }
+            *slot = Some(value.into());
+        }
+        Ok(coin)
+    }
+}
+
+impl Eval for Coin {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        let side = match ctx.rng.gen::<bool>() {
+            true => Side::Heads,
+            false => Side::Tails,
+        };
+        let value = match side {
+            Side::Heads => self.heads.clone(),
+            Side::Tails => self.tails.clone(),
+        };
+        let res = CoinSample {
+            side,
+            value,
+            ascii: ctx.ascii,
+        };
+        ctx.trace(|| format!("coin: {res:#}"));
+        Sample::expr(Box::new(res)).into()
+    }
+}
+
+/// Result of a coin toss
+///
+/// The [`Display`] [alternate modifier](std::fmt#sign0) will print it
+/// without colors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CoinSample {
+    side: Side,
+    /// Mapped value of the side
+    value: Option<Rc<str>>,
+    /// Unmapped sides are `H` and `T`
+    ascii: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Heads,
+    Tails,
+}
+
+impl CoinSample {
+    fn as_str(&self) -> &str {
+        match (&self.value, self.side, self.ascii) {
+            (Some(v), _, _) => v,
+            (None, Side::Heads, false) => "heads",
+            (None, Side::Tails, false) => "tails",
+            (None, Side::Heads, true) => "H",
+            (None, Side::Tails, true) => "T",
+        }
+    }
+}
+
+impl ExprSample for CoinSample {
+    fn kind(&self) -> &'static str {
+        "coin"
+    }
+
+    fn expression(&self) -> String {
+        "coin".to_string()
+    }
+}
+
+impl Display for CoinSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = self.as_str();
+        if f.alternate() {
+            return f.write_str(s);
+        }
+        match self.side {
+            Side::Heads => write!(f, "{}", s.green().bold()),
+            Side::Tails => write!(f, "{}", s.purple().bold()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Algorithm, Generator};
+    use test_case::test_case;
+
+    fn toss(coin: &str, seed: u64) -> Sample {
+        let coin = coin.parse::<Coin>().expect("failed to parse");
+        let mut rng = Generator::new(Algorithm::Pcg, Some(seed));
+        match coin.eval(&mut Ctx::new(&mut rng, None)) {
+            EvalRes::Single(s) => s,
+            _ => panic!("not a single sample"),
+        }
+    }
+
+    #[test]
+    fn no_escape_codes_in_value() {
+        for seed in 0..10 {
+            let sample = toss("coin", seed);
+            let value = format!("{sample:#}");
+            assert!(value == "heads" || value == "tails", "{value:?}");
+            assert_eq!(sample.value(), value);
+            assert!(sample.to_string().contains('\x1b'));
+        }
+    }
+
+    #[test]
+    fn mapped_values() {
+        let mut seen = Vec::new();
+        for seed in 0..20 {
+            let mapped = toss("coin heads=10 tails=0", seed).value();
+            let plain = toss("coin", seed).value();
+            match plain.as_str() {
+                "heads" => assert_eq!(mapped, "10"),
+                "tails" => assert_eq!(mapped, "0"),
+                _ => panic!("unexpected {plain:?}"),
+            }
+            seen.push(mapped);
+        }
+        assert!(seen.iter().any(|v| v == "10") && seen.iter().any(|v| v == "0"));
+    }
+
+    #[test]
+    fn partially_mapped() {
+        for seed in 0..10 {
+            let value = toss("coin  tails=nope", seed).value();
+            assert!(value == "heads" || value == "nope", "{value:?}");
+        }
+    }
 
-pub fn toss_coin(rng: &mut Pcg) -> Sample {
-    const HEADS: &str = "heads";
-    const TAILS: &str = "tails";
-    let res = match rng.gen::<bool>() {
-        true => HEADS.green().bold().to_string(),
-        false => TAILS.purple().bold().to_string(),
-    };
-    Sample::text(res.into())
+    #[test_case("coin heads=1 heads=2" ; "duplicate")]
+    #[test_case("coin edge=1" ; "unknown side")]
+    fn invalid(s: &str) {
+        assert!(matches!(s.parse::<Coin>(), Err(CoinParseError::Invalid(_))));
+    }
 }
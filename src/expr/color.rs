@@ -1,14 +1,137 @@
-use owo_colors::OwoColorize;
+use owo_colors::{DynColors, OwoColorize, XtermColors};
 use rand::Rng;
 
-use crate::{Pcg, Sample};
+use crate::{
+    eval::{Ctx, ExprSample},
+    Sample,
+};
 
-pub fn gen_color(rng: &mut Pcg) -> Sample {
-    let r: u8 = rng.gen();
-    let g: u8 = rng.gen();
-    let b: u8 = rng.gen();
+pub fn gen_color(ctx: &mut Ctx) -> Sample {
+    let r: u8 = ctx.rng.gen();
+    let g: u8 = ctx.rng.gen();
+    let b: u8 = ctx.rng.gen();
+    ctx.trace(|| format!("color: {r:02X}{g:02X}{b:02X}"));
 
-    let hex = format!(" {r:02X}{g:02X}{b:02X} ");
-    let color = owo_colors::DynColors::Rgb(r, g, b);
-    Sample::text(hex.bold().on_color(color).to_string().into())
+    let swatch = if ctx.truecolor {
+        DynColors::Rgb(r, g, b)
+    } else {
+        DynColors::Xterm(XtermColors::from(rgb_to_xterm(r, g, b)))
+    };
+    Sample::expr(Box::new(ColorSample {
+        rgb: (r, g, b),
+        swatch,
+        ascii: ctx.ascii,
+    }))
+}
+
+struct ColorSample {
+    rgb: (u8, u8, u8),
+    /// Background color, may not be exactly `rgb`
+    swatch: DynColors,
+    /// Only the hex code, like `#FF8000`, without the swatch
+    ascii: bool,
+}
+
+impl ExprSample for ColorSample {
+    fn kind(&self) -> &'static str {
+        "color"
+    }
+
+    fn expression(&self) -> String {
+        "color".to_string()
+    }
+
+    fn rgb(&self) -> Option<(u8, u8, u8)> {
+        Some(self.rgb)
+    }
+}
+
+impl std::fmt::Display for ColorSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (r, g, b) = self.rgb;
+        if self.ascii {
+            return write!(f, "#{r:02X}{g:02X}{b:02X}");
+        }
+        let hex = format!(" {r:02X}{g:02X}{b:02X} ");
+        write!(f, "{}", hex.bold().on_color(self.swatch))
+    }
+}
+
+/// Nearest color in the xterm 256 color palette
+///
+/// Only the 6x6x6 color cube and the grayscale ramp are used, the first 16
+/// colors depend on the terminal theme.
+fn rgb_to_xterm(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest_level = |c: u8| {
+        (0..LEVELS.len())
+            .min_by_key(|&i| LEVELS[i].abs_diff(c))
+            .unwrap()
+    };
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a.abs_diff(b) as u32).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+
+    let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+    let cube = 16 + 36 * ri + 6 * gi + bi;
+    let cube_distance = distance((LEVELS[ri], LEVELS[gi], LEVELS[bi]));
+
+    // grays from 8 to 238 in steps of 10
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_i = (avg.saturating_sub(3) / 10).min(23) as u8;
+    let gray = 8 + 10 * gray_i;
+    let gray_distance = distance((gray, gray, gray));
+
+    if gray_distance < cube_distance {
+        232 + gray_i
+    } else {
+        cube as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(255, 0, 0 => 196 ; "red")]
+    #[test_case(0, 0, 0 => 16 ; "black")]
+    #[test_case(255, 255, 255 => 231 ; "white")]
+    #[test_case(128, 128, 128 => 244 ; "gray")]
+    #[test_case(0, 95, 135 => 24 ; "exact cube")]
+    #[test_case(100, 140, 210 => 68 ; "nearest cube")]
+    fn xterm(r: u8, g: u8, b: u8) -> u8 {
+        rgb_to_xterm(r, g, b)
+    }
+
+    #[test]
+    fn hex_is_exact() {
+        let sample = ColorSample {
+            rgb: (100, 140, 210),
+            swatch: DynColors::Xterm(XtermColors::from(68)),
+            ascii: false,
+        };
+        assert_eq!(crate::ansi::strip(&sample.to_string()), " 648CD2 ");
+    }
+
+    #[test]
+    fn channels() {
+        let output = crate::run_query("color, a / all o").unwrap();
+        let rgb = output[0].rgb().expect("no channels");
+        let hex = format!("{:02X}{:02X}{:02X}", rgb.0, rgb.1, rgb.2);
+        assert_eq!(output[0].value().trim(), hex);
+        assert_eq!(output[1].rgb(), None);
+    }
+
+    #[test]
+    fn palette() {
+        let mut state = crate::State::with_seed(1);
+        state.set_truecolor(false);
+        let color = state.eval_expression("color").unwrap().to_string();
+        assert!(color.contains("48;5;"), "{color:?}");
+        state.set_truecolor(true);
+        let color = state.eval_expression("color").unwrap().to_string();
+        assert!(color.contains("48;2;"), "{color:?}");
+    }
 }
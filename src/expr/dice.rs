@@ -3,30 +3,98 @@
 use owo_colors::OwoColorize;
 use rand::Rng;
 
+use crate::eval::Ctx;
 use crate::eval::Eval;
 use crate::eval::EvalRes;
+use crate::eval::ExprSample;
 use crate::eval::Sample;
+use crate::expr::interval::{Float, Num};
+use crate::format::NumberFormat;
 use crate::regex;
-use crate::Pcg;
+use std::collections::BTreeMap;
 use std::fmt::Write;
+use std::rc::Rc;
 use std::{fmt::Display, str::FromStr};
 
 /// A description of a dice roll
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Roll {
     /// Number of dice
     amount: u16,
     /// Number of sides
     sides: u16,
+    /// Custom values of the sides, like `d[2,3,3,4,4,5]`
+    ///
+    /// If not set, the sides are `1..=sides`.
+    faces: Option<Rc<[u16]>>,
     /// Use exploding dice
     ///
     /// If a die results in it's maximum value (number of sides) an extra die
     /// is rolled.
     exploding: bool,
+    /// Maximum number of extra dice each die can add when exploding
+    ///
+    /// It's never more than [`MAX_EXPLOSIONS`].
+    explode_cap: Option<u16>,
+    /// Reroll once the dice that result in a 1
+    ///
+    /// The reroll happens before any die is selected. Same as [`Reroll`] of 1s
+    /// once, but written `e`.
+    emphasis: bool,
+    /// See [`Reroll`]
+    reroll: Option<Reroll>,
     /// See [`SelectDice`]
     select: Option<SelectDice>,
+    /// See [`Reduce`]
+    reduce: Option<Reduce>,
+    /// See [`Success`]
+    success: Option<Success>,
     /// Amount to add/subtract to the sum of the rolls
-    modifier: i32,
+    modifier: i64,
+    /// Each modifier as written, only if some has a label
+    labeled: Option<Rc<[Modifier]>>,
+    /// Percent to increase/decrease the total, after the modifier
+    ///
+    /// It's never more than [`MAX_PERCENT`], up or down.
+    percent: i32,
+    /// Wrap the total to `0..modulo`
+    modulo: Option<i32>,
+    /// Divide the total, giving a float if it's not a whole number
+    divisor: Option<u16>,
+    /// See [`Rounding`]
+    rounding: Option<Rounding>,
+    /// Target to compare the total with, like `1d20+5 vs 15`
+    ///
+    /// Meeting the target is a success.
+    versus: Option<i32>,
+    /// Written as roll and keep, like `10k4`
+    roll_keep: bool,
+}
+
+/// A modifier with an optional label, like `+2[bless]`
+///
+/// The label is only shown, the modifier is added like any other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Modifier {
+    value: i32,
+    label: Option<Rc<str>>,
+}
+
+/// Reroll once the dice that result in `at` or less
+///
+/// The reroll happens before any die is selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reroll {
+    at: u16,
+    policy: RerollPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RerollPolicy {
+    /// Keep the new result, even if it's worse, `ro`
+    Once,
+    /// Keep the best of both results, `rb`
+    Better,
 }
 
 /// Select a subset of the total dice rolled
@@ -52,6 +120,65 @@ enum SelectWhich {
     Low,
 }
 
+/// Use a single die instead of the sum of the dice
+///
+/// It's applied after [`SelectDice`]. Unlike keeping one die, this never
+/// sums, so it's clear in the breakdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reduce {
+    /// Highest die, `^`
+    Max,
+    /// Lowest die, `v`
+    Min,
+}
+
+/// Round the result of the division to a whole number, like `3d6 div 2 floor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rounding {
+    /// Down, `floor`
+    Floor,
+    /// Up, `ceil`
+    Ceil,
+    /// To the nearest, half away from zero, `round`
+    Round,
+}
+
+impl Rounding {
+    fn apply(self, total: i64, divisor: i64) -> i64 {
+        match self {
+            Rounding::Floor => total.div_euclid(divisor),
+            Rounding::Ceil => -(-total).div_euclid(divisor),
+            Rounding::Round => (total as f64 / divisor as f64).round() as i64,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Rounding::Floor => "floor",
+            Rounding::Ceil => "ceil",
+            Rounding::Round => "round",
+        }
+    }
+}
+
+/// Count the dice that meet a target instead of summing them
+///
+/// It's applied to the kept dice, including the ones added by exploding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Success {
+    cmp: SuccessCmp,
+    target: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SuccessCmp {
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Eq,
+}
+
 /// Error from [`Roll::from_str`]
 #[derive(Debug)]
 pub enum RollParseError {
@@ -74,51 +201,158 @@ impl FromStr for Roll {
     type Err = RollParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let re = regex!(r"\A(\d+)?d(\d+|%)(!)?(([kd][hl]?)(\d+)?)?((?:[+-]\d+)+)?\z");
+        let re = regex!(
+            r"(?x)
+            \A
+            (?:
+                (?<amount>\d+)?
+                d(?<sides>\d+|%|\[(?<faces>[\d\s,-]*)\])
+                (?<exploding>!(?:x(?<explode_cap>\d+))?)?
+                (?<emphasis>e)?
+                (?<reroll>r(?<reroll_policy>[ob])(?<reroll_at>\d+))?
+                (?<select>(?<select_kind>[kd][hl]?)(?<select_amount>-?\d+)?)?
+                (?<reduce>[\^v])?
+                (?<success>
+                    (?<success_cmp>>=|<=|[<>=])(?<success_target>\d+)
+                    (?<late_exploding>!(?:x(?<late_explode_cap>\d+))?)?
+                )?
+            |
+                (?<rk_roll>\d+)k(?<rk_keep>\d+)(?<rk_emphasis>e)?
+            )
+            (?<modifier>(?:[+-]\d+(?:\[[^\]]*\])?)+)?
+            (?:\s*(?<percent>[+-]\d+)%)?
+            (?:\s*%\s*(?<modulo>\d+))?
+            (?:\s*div\s*(?<divisor>\d+)(?:\s*(?<rounding>floor|ceil|round))?)?
+            (?:\s+vs\s+(?<versus>-?\d+))?
+            \z"
+        );
 
-        let caps = re.captures(s).ok_or(RollParseError::NoMatch)?;
+        let s = normalize(s);
+        let caps = re.captures(&s).ok_or(RollParseError::NoMatch)?;
 
-        let amount = caps.get(1).map_or(Ok(1), |m| {
-            m.as_str()
-                .parse::<u16>()
-                .map_err(|e| RollParseError::Invalid(format!("bad amount: {e}")))
-                .and_then(|a| {
-                    if a == 0 {
-                        Err(RollParseError::Invalid("amount can't be 0".to_string()))
-                    } else {
-                        Ok(a)
-                    }
-                })
-        })?;
-        let sides = match &caps[2] {
-            "%" => 100,
-            num => num
-                .parse::<u16>()
-                .map_err(|e| RollParseError::Invalid(format!("bad number of sides: {e}")))
-                .and_then(|s| {
-                    if s == 0 {
-                        Err(RollParseError::Invalid(
-                            "number of sides can't be 0".to_string(),
-                        ))
-                    } else {
-                        Ok(s)
-                    }
-                })?,
+        // roll and keep are exploding d10s keeping the highest
+        let roll_keep = caps.name("rk_roll").is_some();
+
+        let amount_of_dice = caps
+            .name("amount")
+            .or(caps.name("rk_roll"))
+            .map_or(Ok(1), |m| {
+                m.as_str()
+                    .parse::<u16>()
+                    .map_err(|e| RollParseError::Invalid(format!("bad amount: {e}")))
+                    .and_then(|a| {
+                        if a == 0 {
+                            Err(RollParseError::Invalid("amount can't be 0".to_string()))
+                        } else {
+                            Ok(a)
+                        }
+                    })
+            })?;
+        let faces = caps
+            .name("faces")
+            .map(|m| parse_faces(m.as_str()))
+            .transpose()?;
+        let sides = if let Some(faces) = &faces {
+            faces.len() as u16 // checked in parse_faces
+        } else {
+            match caps.name("sides").map_or("10", |m| m.as_str()) {
+                "%" => 100,
+                num => num
+                    .parse::<u16>()
+                    .map_err(|e| RollParseError::Invalid(format!("bad number of sides: {e}")))
+                    .and_then(|s| {
+                        if s == 0 {
+                            Err(RollParseError::Invalid(
+                                "number of sides can't be 0".to_string(),
+                            ))
+                        } else {
+                            Ok(s)
+                        }
+                    })?,
+            }
         };
 
-        let exploding = caps.get(3).is_some();
+        // pool notation has the explosion after the target, like `8d10>=7!`
+        if caps.name("exploding").is_some() && caps.name("late_exploding").is_some() {
+            return Err(RollParseError::Invalid(
+                "exploding written twice".to_string(),
+            ));
+        }
+        let exploding =
+            roll_keep || caps.name("exploding").is_some() || caps.name("late_exploding").is_some();
+        let explode_cap = caps
+            .name("explode_cap")
+            .or(caps.name("late_explode_cap"))
+            .map(|m| {
+                m.as_str()
+                    .parse::<u16>()
+                    .map_err(|e| RollParseError::Invalid(format!("bad explosion cap: {e}")))
+                    .and_then(|c| {
+                        if c == 0 {
+                            Err(RollParseError::Invalid(
+                                "explosion cap can't be 0".to_string(),
+                            ))
+                        } else if c > MAX_EXPLOSIONS {
+                            Err(RollParseError::Invalid(format!(
+                                "explosion cap can't be more than {MAX_EXPLOSIONS}"
+                            )))
+                        } else {
+                            Ok(c)
+                        }
+                    })
+            })
+            .transpose()?;
+        let emphasis = caps.name("emphasis").is_some() || caps.name("rk_emphasis").is_some();
+        let reroll = if caps.name("reroll").is_some() {
+            if emphasis {
+                return Err(RollParseError::Invalid(
+                    "emphasis is already a reroll".to_string(),
+                ));
+            }
+            let policy = match &caps["reroll_policy"] {
+                "o" => RerollPolicy::Once,
+                "b" => RerollPolicy::Better,
+                _ => panic!("unknown reroll policy"),
+            };
+            let at = caps["reroll_at"]
+                .parse::<u16>()
+                .map_err(|e| RollParseError::Invalid(format!("bad reroll value: {e}")))?;
+            if at == 0 {
+                return Err(RollParseError::Invalid(
+                    "reroll value can't be 0".to_string(),
+                ));
+            }
+            Some(Reroll { at, policy })
+        } else {
+            None
+        };
 
-        let select = if caps.get(4).is_some() {
-            let (action, which) = match &caps[5] {
+        let select = if let Some(keep) = caps.name("rk_keep") {
+            let amount = keep
+                .as_str()
+                .parse::<u16>()
+                .map_err(|e| RollParseError::Invalid(format!("bad keep amount: {e}")))?;
+            if amount == 0 {
+                return Err(RollParseError::Invalid(
+                    "keep amount can't be 0".to_string(),
+                ));
+            }
+            Some(SelectDice {
+                action: SelectAction::Keep,
+                which: SelectWhich::High,
+                amount,
+            })
+        } else if caps.name("select").is_some() {
+            let (mut action, mut which) = match &caps["select_kind"] {
                 "k" | "kh" => (SelectAction::Keep, SelectWhich::High),
                 "kl" => (SelectAction::Keep, SelectWhich::Low),
                 "d" | "dl" => (SelectAction::Drop, SelectWhich::Low),
                 "dh" => (SelectAction::Drop, SelectWhich::High),
                 _ => panic!("unknown select kind"),
             };
-            let amount = caps.get(6).map_or(Ok(1), |m| {
+            let amount = caps.name("select_amount").map_or(Ok(1), |m| {
                 m.as_str()
-                    .parse::<u16>()
+                    .parse::<i32>()
                     .map_err(|e| RollParseError::Invalid(format!("bad select amount: {e}")))
                     .and_then(|a| {
                         if a == 0 {
@@ -130,6 +364,26 @@ impl FromStr for Roll {
                         }
                     })
             })?;
+            // keep all but `n` is the same as dropping the other end
+            if amount < 0 {
+                if action == SelectAction::Drop {
+                    return Err(RollParseError::Invalid(
+                        "drop amount can't be negative".to_string(),
+                    ));
+                }
+                if amount.unsigned_abs() >= amount_of_dice as u32 {
+                    return Err(RollParseError::Invalid(
+                        "keeping all but more dice than rolled".to_string(),
+                    ));
+                }
+                action = SelectAction::Drop;
+                which = match which {
+                    SelectWhich::High => SelectWhich::Low,
+                    SelectWhich::Low => SelectWhich::High,
+                };
+            }
+            let amount = u16::try_from(amount.unsigned_abs())
+                .map_err(|e| RollParseError::Invalid(format!("bad select amount: {e}")))?;
             Some(SelectDice {
                 action,
                 which,
@@ -139,23 +393,143 @@ impl FromStr for Roll {
             None
         };
 
-        let modifier = caps.get(7).map_or(Ok(0), |m| {
-            let re = regex!(r"[+-]\d+");
-            re.find_iter(m.as_str())
-                .map(|m| {
-                    m.as_str()
-                        .parse::<i32>()
-                        .map_err(|e| RollParseError::Invalid(format!("bad modifier: {e}")))
+        let reduce = caps.name("reduce").map(|m| match m.as_str() {
+            "^" => Reduce::Max,
+            "v" => Reduce::Min,
+            _ => panic!("unknown reducer"),
+        });
+
+        let success = if caps.name("success").is_some() {
+            if reduce.is_some() {
+                return Err(RollParseError::Invalid(
+                    "can't count successes of a single die".to_string(),
+                ));
+            }
+            let cmp = match &caps["success_cmp"] {
+                ">" => SuccessCmp::Greater,
+                ">=" => SuccessCmp::GreaterEq,
+                "<" => SuccessCmp::Less,
+                "<=" => SuccessCmp::LessEq,
+                "=" => SuccessCmp::Eq,
+                _ => panic!("unknown success comparison"),
+            };
+            let target = caps["success_target"]
+                .parse::<u16>()
+                .map_err(|e| RollParseError::Invalid(format!("bad success target: {e}")))?;
+            Some(Success { cmp, target })
+        } else {
+            None
+        };
+
+        let mut modifiers = Vec::new();
+        if let Some(m) = caps.name("modifier") {
+            let re = regex!(r"([+-]\d+)(?:\[([^\]]*)\])?");
+            for caps in re.captures_iter(m.as_str()) {
+                let value = caps[1]
+                    .parse::<i32>()
+                    .map_err(|e| RollParseError::Invalid(format!("bad modifier: {e}")))?;
+                let label = match caps.get(2).map(|l| l.as_str().trim()) {
+                    Some("") => {
+                        return Err(RollParseError::Invalid("empty modifier label".to_string()))
+                    }
+                    label => label.map(Rc::from),
+                };
+                modifiers.push(Modifier { value, label });
+            }
+        }
+        let modifier = modifiers.iter().map(|m| i64::from(m.value)).sum();
+        let labeled = modifiers
+            .iter()
+            .any(|m| m.label.is_some())
+            .then(|| Rc::from(modifiers));
+
+        let percent = caps.name("percent").map_or(Ok(0), |m| {
+            m.as_str()
+                .parse::<i32>()
+                .map_err(|e| RollParseError::Invalid(format!("bad percent: {e}")))
+                .and_then(|p| {
+                    if p.unsigned_abs() > MAX_PERCENT {
+                        Err(RollParseError::Invalid(format!(
+                            "percent can't be more than {MAX_PERCENT}"
+                        )))
+                    } else {
+                        Ok(p)
+                    }
                 })
-                .sum::<Result<i32, _>>()
         })?;
 
+        let modulo = caps
+            .name("modulo")
+            .map(|m| {
+                m.as_str()
+                    .parse::<i32>()
+                    .map_err(|e| RollParseError::Invalid(format!("bad modulo: {e}")))
+                    .and_then(|m| {
+                        if m == 0 {
+                            Err(RollParseError::Invalid("modulo can't be 0".to_string()))
+                        } else {
+                            Ok(m)
+                        }
+                    })
+            })
+            .transpose()?;
+
+        let divisor = caps
+            .name("divisor")
+            .map(|m| {
+                m.as_str()
+                    .parse::<u16>()
+                    .map_err(|e| RollParseError::Invalid(format!("bad divisor: {e}")))
+                    .and_then(|d| {
+                        if d == 0 {
+                            Err(RollParseError::Invalid("divisor can't be 0".to_string()))
+                        } else {
+                            Ok(d)
+                        }
+                    })
+            })
+            .transpose()?;
+
+        let rounding = caps.name("rounding").map(|m| match m.as_str() {
+            "floor" => Rounding::Floor,
+            "ceil" => Rounding::Ceil,
+            "round" => Rounding::Round,
+            _ => unreachable!("rounding matched by regex"),
+        });
+
+        let versus = caps
+            .name("versus")
+            .map(|m| {
+                if divisor.is_some() {
+                    return Err(RollParseError::Invalid(
+                        "can't compare a divided roll".to_string(),
+                    ));
+                }
+                m.as_str()
+                    .parse::<i32>()
+                    .map_err(|e| RollParseError::Invalid(format!("bad target: {e}")))
+            })
+            .transpose()?;
+
         Ok(Roll {
-            amount,
+            amount: amount_of_dice,
             sides,
+            faces,
             exploding,
+            explode_cap,
+            emphasis,
+            reroll,
             select,
+            reduce,
+            success,
             modifier,
+            labeled,
+            percent,
+            modulo,
+            divisor,
+            rounding,
+            versus,
+            roll_keep,
         })
     }
 }
@@ -174,12 +548,39 @@ impl Display for Roll {
             _ => BrightWhite,
         };
 
+        if let Some(select) = self.select.filter(|_| self.roll_keep) {
+            write!(
+                f,
+                "{}{}{}",
+                self.amount.color(color).italic(),
+                "k".color(color),
+                select.amount.color(color)
+            )?;
+            if self.emphasis {
+                f.write_char('e')?;
+            }
+            return self.fmt_tail(f);
+        }
+
         if self.amount > 1 {
             write!(f, "{}", self.amount.color(color).italic())?;
         }
-        write!(f, "{}{}", "d".color(color), self.sides.color(color))?;
+        write!(f, "{}{}", "d".color(color), self.fmt_sides().color(color))?;
         if self.exploding {
             f.write_char('!')?;
+            if let Some(cap) = self.explode_cap {
+                write!(f, "x{cap}")?;
+            }
+        }
+        if self.emphasis {
+            f.write_char('e')?;
+        }
+        if let Some(reroll) = self.reroll {
+            let policy = match reroll.policy {
+                RerollPolicy::Once => "ro",
+                RerollPolicy::Better => "rb",
+            };
+            write!(f, "{policy}{}", reroll.at)?;
         }
         if let Some(select) = self.select {
             let s = match (select.action, select.which) {
@@ -189,14 +590,152 @@ impl Display for Roll {
                 (SelectAction::Drop, SelectWhich::Low) => "d",
             };
             f.write_str(s)?;
-            if select.amount > 1 {
+            // a negative modifier would be read as the amount
+            if select.amount > 1 || self.modifier < 0 {
                 write!(f, "{}", select.amount)?;
             }
         }
-        print_modifier(f, self.modifier)?;
+        if let Some(reduce) = self.reduce {
+            f.write_char(reduce.symbol())?;
+        }
+        if let Some(success) = self.success {
+            write!(f, "{success}")?;
+        }
+        self.fmt_tail(f)
+    }
+}
 
+impl Success {
+    fn is_success(self, val: u16) -> bool {
+        match self.cmp {
+            SuccessCmp::Greater => val > self.target,
+            SuccessCmp::GreaterEq => val >= self.target,
+            SuccessCmp::Less => val < self.target,
+            SuccessCmp::LessEq => val <= self.target,
+            SuccessCmp::Eq => val == self.target,
+        }
+    }
+}
+
+impl Display for Success {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cmp = match self.cmp {
+            SuccessCmp::Greater => ">",
+            SuccessCmp::GreaterEq => ">=",
+            SuccessCmp::Less => "<",
+            SuccessCmp::LessEq => "<=",
+            SuccessCmp::Eq => "=",
+        };
+        write!(f, "{cmp}{}", self.target)
+    }
+}
+
+impl Reduce {
+    fn symbol(self) -> char {
+        match self {
+            Reduce::Max => '^',
+            Reduce::Min => 'v',
+        }
+    }
+}
+
+impl Roll {
+    /// Sides as written, with the custom faces compacted
+    fn fmt_sides(&self) -> String {
+        let Some(faces) = &self.faces else {
+            return self.sides.to_string();
+        };
+        let mut s = String::from("[");
+        let mut i = 0;
+        while i < faces.len() {
+            if i > 0 {
+                s.push(',');
+            }
+            // runs of 3 or more consecutive numbers are a range
+            let mut j = i;
+            while j + 1 < faces.len() && faces[j + 1] == faces[j] + 1 {
+                j += 1;
+            }
+            if j - i >= 2 {
+                write!(s, "{}-{}", faces[i], faces[j]).unwrap();
+                i = j + 1;
+            } else {
+                write!(s, "{}", faces[i]).unwrap();
+                i += 1;
+            }
+        }
+        s.push(']');
+        s
+    }
+
+    fn roll_die(&self, ctx: &mut Ctx) -> u16 {
+        match &self.faces {
+            Some(faces) => faces[ctx.rng.gen_range(0..faces.len())],
+            None => ctx.rng.gen_range(1..=self.sides),
+        }
+    }
+
+    /// Biggest value a die can roll
+    fn max_face(&self) -> u16 {
+        match &self.faces {
+            Some(faces) => faces.iter().copied().max().unwrap_or_default(),
+            None => self.sides,
+        }
+    }
+
+    /// Writes what is applied to the sum of the dice
+    ///
+    /// In the `breakdown` of a result, the labels of the modifiers are like
+    /// `+2 (bless)` instead of `+2[bless]`.
+    fn fmt_terms(&self, f: &mut std::fmt::Formatter<'_>, breakdown: bool) -> std::fmt::Result {
+        match &self.labeled {
+            Some(modifiers) => {
+                for m in modifiers.iter() {
+                    print_modifier(f, m.value.into())?;
+                    match &m.label {
+                        Some(label) if breakdown => write!(f, " ({label})")?,
+                        Some(label) => write!(f, "[{label}]")?,
+                        None => {}
+                    }
+                }
+            }
+            None => print_modifier(f, self.modifier)?,
+        }
+        if self.percent != 0 {
+            f.write_char(' ')?;
+            print_modifier(f, self.percent.into())?;
+            f.write_char('%')?;
+        }
+        if let Some(modulo) = self.modulo {
+            write!(f, " % {modulo}")?;
+        }
+        if let Some(divisor) = self.divisor {
+            write!(f, " div {divisor}")?;
+        }
+        if let Some(rounding) = self.rounding {
+            write!(f, " {}", rounding.name())?;
+        }
         Ok(())
     }
+
+    /// Writes the terms and the `vs` target
+    fn fmt_tail(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_terms(f, false)?;
+        if let Some(target) = self.versus {
+            write!(f, " vs {target}")?;
+        }
+        Ok(())
+    }
+
+    /// If the result is always a whole number
+    pub(super) fn is_integer(&self) -> bool {
+        self.divisor.is_none() || self.rounding.is_some()
+    }
+
+    /// If the total is compared with a target, like `1d20 vs 15`
+    pub(super) fn has_target(&self) -> bool {
+        self.versus.is_some()
+    }
 }
 
 /// Result of a dice roll evaluation
@@ -204,9 +743,11 @@ impl Display for Roll {
 /// The [`Display`] [alternate modifier](std::fmt#sign0) will only print
 /// [`RollResult::total`].
 #[derive(Debug, Clone, PartialEq, Eq)]
-struct RollSample {
+pub(super) struct RollSample {
     roll: Roll,
     dice: Vec<Die>,
+    /// Format of the total
+    fmt: NumberFormat,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -216,19 +757,58 @@ struct Die {
 }
 
 impl Eval for Roll {
-    fn eval(&self, rng: &mut Pcg) -> EvalRes {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        Sample::expr(Box::new(self.roll(ctx))).into()
+    }
+}
+
+impl Roll {
+    pub(super) fn roll(&self, ctx: &mut Ctx) -> RollSample {
         let mut dice = Vec::new();
+        let reroll = self.effective_reroll();
 
         for _ in 0..self.amount {
+            let mut explosions = 0;
             loop {
-                let val = rng.gen_range(1..=self.sides);
+                let mut val = self.roll_die(ctx);
+                ctx.trace(|| format!("d{}: {val}", self.fmt_sides()));
+                if let Some(reroll) = reroll.filter(|r| explosions == 0 && val <= r.at) {
+                    let new = self.roll_die(ctx);
+                    ctx.trace(|| format!("d{} reroll: {new}", self.fmt_sides()));
+                    val = match reroll.policy {
+                        RerollPolicy::Once => new,
+                        RerollPolicy::Better => val.max(new),
+                    };
+                }
                 dice.push(Die { val, take: true });
-                if !(self.exploding && val == self.sides) {
+                if !(self.exploding && val == self.max_face()) {
+                    break;
+                }
+                if explosions >= self.explode_cap.unwrap_or(MAX_EXPLOSIONS) {
                     break;
                 }
+                explosions += 1;
             }
         }
+        self.select_dice(&mut dice);
 
+        RollSample {
+            roll: self.clone(),
+            dice,
+            fmt: ctx.fmt,
+        }
+    }
+
+    /// The reroll, also for emphasis
+    fn effective_reroll(&self) -> Option<Reroll> {
+        self.reroll.or(self.emphasis.then_some(Reroll {
+            at: 1,
+            policy: RerollPolicy::Once,
+        }))
+    }
+
+    /// Marks the dice not taken by the [`SelectDice`], if any
+    fn select_dice(&self, dice: &mut [Die]) {
         if let Some(select) = &self.select {
             let n = select.amount as usize;
             dice.sort_unstable();
@@ -248,8 +828,111 @@ impl Eval for Roll {
                 }
             }
         }
+    }
+}
+
+/// Most extra dice a die can add by exploding, even without a cap
+///
+/// A die that always explodes, like `d1!`, stops here.
+const MAX_EXPLOSIONS: u16 = 100;
+
+/// Max percent to increase or decrease a roll, so the total always fits
+const MAX_PERCENT: u32 = 10_000;
+
+/// Default max number of dice combinations of [`Roll::enumerate_outcomes`]
+pub const MAX_OUTCOMES: u64 = 1_000_000;
+
+impl Roll {
+    /// Exact number of ways to get each result
+    ///
+    /// All the combinations of the faces of the dice are checked, so each
+    /// count divided by the sum of all of them is the exact probability.
+    /// `None` if the roll is exploding, the result is not a whole number, it
+    /// doesn't fit an `i32` or there are more than [`MAX_OUTCOMES`]
+    /// combinations.
+    pub fn enumerate_outcomes(&self) -> Option<BTreeMap<i32, u64>> {
+        self.enumerate_outcomes_with_limit(MAX_OUTCOMES)
+    }
+
+    /// Like [`Roll::enumerate_outcomes`], with at most `limit` combinations
+    pub fn enumerate_outcomes_with_limit(&self, limit: u64) -> Option<BTreeMap<i32, u64>> {
+        if self.exploding || !self.is_integer() {
+            return None;
+        }
+        let die = self.die_outcomes();
+        let combinations = (die.len() as u64).checked_pow(self.amount.into())?;
+        if combinations > limit {
+            return None;
+        }
+
+        let mut outcomes = BTreeMap::new();
+        // index in `die` of the value of each die
+        let mut indices = vec![0; self.amount as usize];
+        loop {
+            let mut dice = indices
+                .iter()
+                .map(|&i| Die {
+                    val: die[i].0,
+                    take: true,
+                })
+                .collect::<Vec<_>>();
+            self.select_dice(&mut dice);
+            let sample = RollSample {
+                roll: self.clone(),
+                dice,
+                fmt: NumberFormat::default(),
+            };
+            let Num::Int(value) = sample.value() else {
+                unreachable!("checked integer roll")
+            };
+            let ways = indices
+                .iter()
+                .try_fold(1u64, |ways, &i| ways.checked_mul(die[i].1))?;
+            let count = outcomes.entry(i32::try_from(value).ok()?).or_insert(0u64);
+            *count = count.checked_add(ways)?;
+
+            // next combination, like counting in base `die.len()`
+            let mut pos = 0;
+            loop {
+                let Some(index) = indices.get_mut(pos) else {
+                    return Some(outcomes);
+                };
+                *index += 1;
+                if *index < die.len() {
+                    break;
+                }
+                *index = 0;
+                pos += 1;
+            }
+        }
+    }
 
-        Sample::expr(Box::new(RollSample { roll: *self, dice })).into()
+    /// Each value of a single die and the number of ways to roll it
+    ///
+    /// With a reroll, every way is a pair of rolls, even if the first one is
+    /// kept, so all of them have the same probability.
+    fn die_outcomes(&self) -> Vec<(u16, u64)> {
+        let faces = match &self.faces {
+            Some(faces) => faces.to_vec(),
+            None => (1..=self.sides).collect(),
+        };
+        let mut ways = BTreeMap::<u16, u64>::new();
+        for &first in &faces {
+            match self.effective_reroll() {
+                Some(reroll) if first <= reroll.at => {
+                    for &second in &faces {
+                        let val = match reroll.policy {
+                            RerollPolicy::Once => second,
+                            RerollPolicy::Better => first.max(second),
+                        };
+                        *ways.entry(val).or_default() += 1;
+                    }
+                }
+                Some(_) => *ways.entry(first).or_default() += faces.len() as u64,
+                None => *ways.entry(first).or_default() += 1,
+            }
+        }
+        ways.into_iter().collect()
     }
 }
 
@@ -284,7 +967,7 @@ impl RollSample {
     }
 
     /// Modifier applied
-    pub fn modifier(&self) -> i32 {
+    pub fn modifier(&self) -> i64 {
         self.roll.modifier
     }
 
@@ -293,31 +976,255 @@ impl RollSample {
         self.roll.exploding
     }
 
-    /// Total value
-    pub fn total(&self) -> i32 {
-        self.dice().map(|v| v as i32).sum::<i32>() + self.roll.modifier
+    /// Value of the die when a single one was rolled and kept
+    ///
+    /// This is the natural roll, before any modifier. With more dice, like in
+    /// an exploding roll, it's `None`.
+    pub fn natural(&self) -> Option<u16> {
+        match self.dice.as_slice() {
+            [die] if die.take => Some(die.val),
+            _ => None,
+        }
+    }
+
+    /// Number of kept dice that meet the target, if counting successes
+    pub fn successes(&self) -> Option<u32> {
+        let success = self.roll.success?;
+        Some(self.dice().filter(|&v| success.is_success(v)).count() as u32)
+    }
+
+    /// Total minus the `vs` target, if any
+    ///
+    /// Zero or more is a success.
+    pub fn margin(&self) -> Option<i64> {
+        Some(self.total() - i64::from(self.roll.versus?))
+    }
+
+    /// Result of the roll
+    ///
+    /// The [`total`](Self::total) divided by the divisor, if any. It's only a
+    /// [`Num::Float`] when the division is not exact and not rounded.
+    pub fn value(&self) -> Num {
+        let total = self.total();
+        match (self.roll.divisor.map(i64::from), self.roll.rounding) {
+            (Some(d), Some(rounding)) => Num::Int(rounding.apply(total, d)),
+            (Some(d), None) if total % d != 0 => Num::Float(total as Float / d as Float),
+            (Some(d), None) => Num::Int(total / d),
+            (None, _) => Num::Int(total),
+        }
+    }
+
+    /// Total value, before the division
+    ///
+    /// The percent is applied after the modifier and rounded half away from
+    /// zero. With a modulo, the total is always in `0..modulo`, even if the sum
+    /// is negative.
+    pub fn total(&self) -> i64 {
+        let dice = self.dice().map(i64::from);
+        let base = match self.roll.reduce {
+            Some(Reduce::Max) => dice.max().unwrap_or_default(),
+            Some(Reduce::Min) => dice.min().unwrap_or_default(),
+            None => match self.successes() {
+                Some(n) => n.into(),
+                None => dice.sum::<i64>(),
+            },
+        };
+        let mut sum = base + self.roll.modifier;
+        if self.roll.percent != 0 {
+            let factor = (100 + self.roll.percent) as f64 / 100.0;
+            sum = (sum as f64 * factor).round() as i64;
+        }
+        match self.roll.modulo {
+            Some(m) => sum.rem_euclid(m.into()),
+            None => sum,
+        }
     }
 }
 
 impl Display for RollSample {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if f.alternate() {
-            return self.total().fmt(f);
+            return match self.margin() {
+                Some(margin) => write!(f, "{}, {}", self.fmt_value(), fmt_margin(margin)),
+                None => f.pad(&self.fmt_value()),
+            };
         }
 
         write!(f, "{}: ", self.roll)?;
 
-        if self.roll.exploding || self.roll.select.is_some() || self.roll.modifier != 0 {
+        if self.roll.exploding
+            || self.roll.emphasis
+            || self.roll.reroll.is_some()
+            || self.roll.select.is_some()
+            || self.roll.reduce.is_some()
+            || self.roll.success.is_some()
+            || self.roll.modifier != 0
+            || self.roll.percent != 0
+            || self.roll.modulo.is_some()
+            || self.roll.divisor.is_some()
+        {
+            // reduced or counted dice are not added
+            let added = self.roll.reduce.is_none() && self.roll.success.is_none();
+            let sep = if added { "+" } else { "," };
             write!(f, "[{}", self.dice[0])?;
             for val in &self.dice[1..] {
-                write!(f, "{}{val}", "+".dimmed())?;
+                write!(f, "{}{val}", sep.dimmed())?;
             }
             write!(f, "]")?;
-            print_modifier(f, self.roll.modifier)?;
+            if let Some(reduce) = self.roll.reduce {
+                f.write_char(reduce.symbol())?;
+            }
+            if let Some(success) = self.roll.success {
+                write!(f, "{success}")?;
+            }
+            self.roll.fmt_terms(f, true)?;
             write!(f, " = ")?;
         }
 
-        write!(f, "{}", self.total())
+        f.write_str(&self.fmt_value())?;
+        if let Some(margin) = self.margin() {
+            write!(f, ", {}", fmt_margin(margin))?;
+        }
+        Ok(())
+    }
+}
+
+/// Like `success by 3` or `fail by 2`
+fn fmt_margin(margin: i64) -> String {
+    if margin >= 0 {
+        format!("success by {margin}")
+    } else {
+        format!("fail by {}", margin.unsigned_abs())
+    }
+}
+
+impl RollSample {
+    fn fmt_value(&self) -> String {
+        match self.value() {
+            Num::Int(n) => self.fmt.int(n),
+            Num::Float(n) => self.fmt.float(n),
+        }
+    }
+}
+
+impl ExprSample for RollSample {
+    fn kind(&self) -> &'static str {
+        "dice"
+    }
+
+    fn expression(&self) -> String {
+        self.roll.to_string()
+    }
+
+    fn number(&self) -> Option<Num> {
+        Some(self.value())
+    }
+
+    fn natural(&self) -> Option<u16> {
+        RollSample::natural(self)
+    }
+
+    fn split_dice(&self) -> Option<Vec<Sample>> {
+        let die: Rc<str> = format!("d{}", self.roll.fmt_sides()).into();
+        let dice = self
+            .dice()
+            .map(|val| {
+                let die = DieSample {
+                    die: Rc::clone(&die),
+                    val,
+                };
+                Sample::expr(Box::new(die))
+            })
+            .collect();
+        Some(dice)
+    }
+
+    fn fmt_pretty(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.roll)?;
+        let natural = self.natural().is_some();
+        for (i, die) in self.dice.iter().enumerate() {
+            if natural {
+                write!(f, "  natural: {}", die.val)?;
+            } else {
+                write!(f, "  die {}: {}", i + 1, die.val)?;
+            }
+            if !die.take {
+                write!(f, " {}", "(dropped)".dimmed().red())?;
+            } else if self.roll.success.is_some_and(|s| s.is_success(die.val)) {
+                write!(f, " {}", "(success)".green())?;
+            }
+            writeln!(f)?;
+        }
+        match self.roll.reduce {
+            Some(Reduce::Max) => writeln!(f, "  highest die only")?,
+            Some(Reduce::Min) => writeln!(f, "  lowest die only")?,
+            None => {}
+        }
+        if let Some(modifiers) = &self.roll.labeled {
+            for m in modifiers.iter() {
+                f.write_str("  modifier: ")?;
+                print_modifier(f, m.value.into())?;
+                if let Some(label) = &m.label {
+                    write!(f, " ({label})")?;
+                }
+                writeln!(f)?;
+            }
+        } else if self.roll.modifier != 0 {
+            f.write_str("  modifier: ")?;
+            print_modifier(f, self.roll.modifier)?;
+            writeln!(f)?;
+        }
+        if self.roll.percent != 0 {
+            f.write_str("  percent: ")?;
+            print_modifier(f, self.roll.percent.into())?;
+            writeln!(f, "%")?;
+        }
+        if let Some(modulo) = self.roll.modulo {
+            writeln!(f, "  modulo: {modulo}")?;
+        }
+        if let Some(divisor) = self.roll.divisor {
+            write!(f, "  divided by: {divisor}")?;
+            if let Some(rounding) = self.roll.rounding {
+                write!(f, ", {}", rounding.name())?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "  total: {}", self.fmt_value().bold())?;
+        if let (Some(target), Some(margin)) = (self.roll.versus, self.margin()) {
+            write!(f, "\n  target: {target}\n  {}", fmt_margin(margin))?;
+        }
+        Ok(())
+    }
+}
+
+/// A single die of a roll, from the `split` option
+struct DieSample {
+    /// Like `d6`
+    die: Rc<str>,
+    val: u16,
+}
+
+impl ExprSample for DieSample {
+    fn kind(&self) -> &'static str {
+        "die"
+    }
+
+    fn expression(&self) -> String {
+        self.die.to_string()
+    }
+
+    fn number(&self) -> Option<Num> {
+        Some(Num::Int(self.val.into()))
+    }
+}
+
+impl Display for DieSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            self.val.fmt(f)
+        } else {
+            write!(f, "{}: {}", self.die, self.val)
+        }
     }
 }
 
@@ -331,7 +1238,66 @@ impl Display for Die {
     }
 }
 
-fn print_modifier(f: &mut std::fmt::Formatter<'_>, modifier: i32) -> std::fmt::Result {
+/// Removes the spaces of a roll, like in `2 d 6 + 1`
+///
+/// The spaces inside custom faces and around `vs` are kept, the regex already
+/// handles them. Spaces between two numbers are also kept, so `2d6 6` is still
+/// invalid.
+fn normalize(s: &str) -> std::borrow::Cow<'_, str> {
+    if !s.contains(char::is_whitespace) {
+        return s.into();
+    }
+    let re = regex!(r"\A(?s:(.*?))(\s+vs\s+\S*)?\z");
+    let caps = re.captures(s).expect("always matches");
+    let mut out = String::with_capacity(s.len());
+    let mut in_faces = false;
+    let mut chars = caps[1].chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => in_faces = true,
+            ']' => in_faces = false,
+            c if c.is_whitespace() && !in_faces => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                let digit_before = out.ends_with(|c: char| c.is_ascii_digit());
+                if !(digit_before && chars.peek().is_some_and(char::is_ascii_digit)) {
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    out.extend(caps.get(2).map(|m| m.as_str()));
+    out.into()
+}
+
+/// Parses custom faces like `1-6, 10`
+fn parse_faces(s: &str) -> Result<Rc<[u16]>, RollParseError> {
+    let bad_face = |e| RollParseError::Invalid(format!("bad face: {e}"));
+    let mut faces = Vec::new();
+    for part in s.split(',') {
+        let part = part.trim();
+        match part.split_once('-') {
+            Some((low, high)) => {
+                let low = low.trim().parse::<u16>().map_err(bad_face)?;
+                let high = high.trim().parse::<u16>().map_err(bad_face)?;
+                if low > high {
+                    return Err(RollParseError::Invalid(format!(
+                        "bad face range: {low} > {high}"
+                    )));
+                }
+                faces.extend(low..=high);
+            }
+            None => faces.push(part.parse::<u16>().map_err(bad_face)?),
+        }
+    }
+    if faces.len() > u16::MAX as usize {
+        return Err(RollParseError::Invalid("too many faces".to_string()));
+    }
+    Ok(faces.into())
+}
+
+fn print_modifier(f: &mut std::fmt::Formatter<'_>, modifier: i64) -> std::fmt::Result {
     match modifier {
         0 => Ok(()),
         1.. => {
@@ -342,3 +1308,556 @@ fn print_modifier(f: &mut std::fmt::Formatter<'_>, modifier: i32) -> std::fmt::R
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Algorithm, Generator};
+    use test_case::test_case;
+
+    fn roll(s: &str, seed: u64) -> RollSample {
+        let roll = s.parse::<Roll>().expect("failed to parse");
+        let mut rng = Generator::new(Algorithm::Pcg, Some(seed));
+        roll.roll(&mut Ctx::new(&mut rng, None))
+    }
+
+    fn outcomes(s: &str) -> Option<Vec<(i32, u64)>> {
+        let roll = s.parse::<Roll>().expect("failed to parse");
+        roll.enumerate_outcomes().map(|o| o.into_iter().collect())
+    }
+
+    #[test]
+    fn enumerate_2d6() {
+        let outcomes = outcomes("2d6").unwrap();
+        assert_eq!(outcomes.iter().map(|(_, n)| n).sum::<u64>(), 36);
+        let expected = (2..=12)
+            .map(|total| (total, 6 - (total - 7i32).unsigned_abs() as u64))
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes, expected);
+    }
+
+    #[test_case("2d20k" => (1, 39, 400) ; "keep high")]
+    #[test_case("2d20kl" => (39, 1, 400) ; "keep low")]
+    #[test_case("1d6ro1" => (1, 7, 36) ; "reroll")]
+    #[test_case("1d6rb2" => (1, 8, 36) ; "reroll better")]
+    #[test_case("d[1,1,2]" => (2, 1, 3) ; "custom faces")]
+    fn enumerate_first_last(s: &str) -> (u64, u64, u64) {
+        let outcomes = outcomes(s).unwrap();
+        let total = outcomes.iter().map(|(_, n)| n).sum();
+        (outcomes[0].1, outcomes.last().unwrap().1, total)
+    }
+
+    #[test_case("3d6!" ; "exploding")]
+    #[test_case("3d6 div 2" ; "division")]
+    #[test_case("10d10" ; "too many")]
+    #[test_case("1d6+2147483647" ; "bigger than i32")]
+    fn enumerate_none(s: &str) {
+        assert_eq!(outcomes(s), None);
+    }
+
+    #[test]
+    fn enumerate_terms() {
+        assert_eq!(outcomes("3d6 div 2 floor").unwrap().len(), 9);
+        assert_eq!(
+            outcomes("4d6>=5").unwrap(),
+            [(0, 256), (1, 512), (2, 384), (3, 128), (4, 16)]
+        );
+        let roll = "4d6".parse::<Roll>().unwrap();
+        assert_eq!(roll.enumerate_outcomes_with_limit(1000), None);
+        let outcomes = roll.enumerate_outcomes_with_limit(1296).unwrap();
+        assert_eq!(outcomes.values().sum::<u64>(), 1296);
+    }
+
+    #[test_case("1d100 % 20" => Some(20) ; "spaces")]
+    #[test_case("1d100%20" => Some(20) ; "no spaces")]
+    #[test_case("d%+5 % 7" => Some(7) ; "with modifier")]
+    #[test_case("1d100" => None ; "none")]
+    #[test_case("1d100 % 0" => panics "failed to parse" ; "zero")]
+    fn parse_modulo(s: &str) -> Option<i32> {
+        s.parse::<Roll>().expect("failed to parse").modulo
+    }
+
+    #[test_case("10d1+15%" => 12 ; "round up")]
+    #[test_case("10d1-15%" => 9 ; "round down")]
+    #[test_case("10d1+5+10%" => 17 ; "after modifier")]
+    #[test_case("10d1-20 -10%" => -9 ; "negative")]
+    #[test_case("10d1+10% % 7" => 4 ; "before modulo")]
+    #[test_case("10d1+10%7" => 6 ; "modulo without percent")]
+    #[test_case("1d1+2147483647+10000%" => 216_895_848_448 ; "bigger than i32")]
+    #[test_case("1d20+10001%" => panics "failed to parse" ; "too big")]
+    #[test_case("1d20+2147483647%" => panics "failed to parse" ; "overflow")]
+    fn percent(s: &str) -> i64 {
+        roll(s, 0).total()
+    }
+
+    #[test]
+    fn percent_seeded() {
+        for seed in 0..20 {
+            let plain = roll("1d20", seed).total();
+            let buffed = roll("1d20 +10%", seed);
+            assert_eq!(buffed.total(), (plain as f64 * 1.1).round() as i64);
+        }
+        let sample = roll("1d20 +10%", 1);
+        let text = crate::ansi::strip(&sample.to_string());
+        assert!(text.starts_with("d20 +10%: ["), "{text}");
+    }
+
+    #[test]
+    fn pretty() {
+        let sample = roll("3d6k2+1", 3);
+        let text = crate::ansi::strip(&format!("{}", Pretty(&sample)));
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1 + 3 + 2, "{text}");
+        assert_eq!(lines[0], "3d6k2+1");
+        assert_eq!(lines.iter().filter(|l| l.ends_with("(dropped)")).count(), 1);
+        assert_eq!(lines[4], "  modifier: +1");
+        assert_eq!(lines[5], format!("  total: {}", sample.total()));
+    }
+
+    #[test]
+    fn natural() {
+        for seed in 0..20 {
+            let sample = roll("1d20+5", seed);
+            let natural = sample.natural().unwrap();
+            assert_eq!(i64::from(natural) + 5, sample.total());
+            let text = crate::ansi::strip(&sample.to_string());
+            assert_eq!(text, format!("d20+5: [{natural}]+5 = {}", natural + 5));
+
+            let text = crate::ansi::strip(&format!("{}", Pretty(&sample)));
+            let lines = text.lines().collect::<Vec<_>>();
+            assert_eq!(lines[1], format!("  natural: {natural}"));
+            assert!(!text.contains("die 1"), "{text}");
+        }
+        assert_eq!(roll("2d20", 0).natural(), None);
+        assert_eq!(roll("2d20kh1", 0).natural(), None);
+        assert_eq!(roll("1d1!x2", 0).natural(), None);
+        assert_eq!(roll("1d1", 0).natural(), Some(1));
+    }
+
+    struct Pretty<'a>(&'a RollSample);
+
+    impl Display for Pretty<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            self.0.fmt_pretty(f)
+        }
+    }
+
+    #[test_case("d6!x3" => (true, Some(3)) ; "capped")]
+    #[test_case("d6!" => (true, None) ; "uncapped")]
+    #[test_case("d6" => (false, None) ; "not exploding")]
+    #[test_case("d6!x0" => panics "failed to parse" ; "zero cap")]
+    fn parse_explode_cap(s: &str) -> (bool, Option<u16>) {
+        let roll = s.parse::<Roll>().expect("failed to parse");
+        (roll.exploding, roll.explode_cap)
+    }
+
+    #[test_case("6d6kh-1", "6d6dl1" ; "keep high")]
+    #[test_case("6d6k-2", "6d6d2" ; "keep high short")]
+    #[test_case("4d6kl-1+3", "4d6dh1+3" ; "keep low with modifier")]
+    fn keep_all_but(s: &str, same: &str) {
+        assert_eq!(s.parse::<Roll>().unwrap(), same.parse::<Roll>().unwrap());
+    }
+
+    #[test_case("6d6dl-1" ; "negative drop")]
+    #[test_case("3d6kh-3" ; "all dice")]
+    #[test_case("3d6kh-5" ; "more than rolled")]
+    fn keep_all_but_invalid(s: &str) {
+        assert!(matches!(s.parse::<Roll>(), Err(RollParseError::Invalid(_))));
+    }
+
+    #[test]
+    fn roll_and_keep() {
+        let rk = "10k4e".parse::<Roll>().unwrap();
+        let same = "10d10!ek4".parse::<Roll>().unwrap();
+        assert_eq!(
+            rk,
+            Roll {
+                roll_keep: true,
+                ..same.clone()
+            }
+        );
+        assert_eq!(crate::ansi::strip(&rk.to_string()), "10k4e");
+        assert_eq!(crate::ansi::strip(&same.to_string()), "10d10!ek4");
+
+        for seed in 0..50 {
+            let sample = roll("10k4e", seed);
+            let kept = sample.dice().collect::<Vec<_>>();
+            assert_eq!(kept.len(), 4);
+            let dropped_max = sample.all_dice().filter(|d| !d.1).map(|d| d.0).max();
+            assert!(dropped_max.unwrap() <= *kept.iter().min().unwrap());
+        }
+    }
+
+    #[test]
+    fn emphasis_rerolls_ones() {
+        let mut rerolls = 0;
+        for seed in 0..50 {
+            let roll = "10d10e".parse::<Roll>().unwrap();
+            let mut rng = Generator::new(Algorithm::Pcg, Some(seed));
+            let mut first_rolls = Vec::new();
+            let mut trace = |msg: &str| {
+                if msg.contains("reroll") {
+                    rerolls += 1;
+                    // the reroll replaces the 1
+                    assert_eq!(first_rolls.pop(), Some("d10: 1".to_string()));
+                }
+                first_rolls.push(msg.to_string());
+            };
+            let sample = roll.roll(&mut Ctx::new(&mut rng, Some(&mut trace)));
+            assert_eq!(sample.dice.len(), 10);
+        }
+        assert!(rerolls > 0);
+    }
+
+    #[test_case("4d6ro1k3" => "4d6ro1k3" ; "once")]
+    #[test_case("d20rb2+1" => "d20rb2+1" ; "better")]
+    #[test_case("d6ro0" => panics "failed to parse" ; "zero")]
+    #[test_case("d6ero1" => panics "failed to parse" ; "with emphasis")]
+    fn parse_reroll(s: &str) -> String {
+        let roll = s.parse::<Roll>().expect("failed to parse");
+        crate::ansi::strip(&roll.to_string())
+    }
+
+    /// Final value of each die and its first roll before any reroll
+    fn roll_with_first(s: &str, seed: u64) -> Vec<(u16, u16)> {
+        let roll = s.parse::<Roll>().unwrap();
+        let mut rng = Generator::new(Algorithm::Pcg, Some(seed));
+        let mut first = Vec::new();
+        let mut trace = |msg: &str| {
+            if !msg.contains("reroll") {
+                let (_, val) = msg.split_once(": ").unwrap();
+                first.push(val.parse::<u16>().unwrap());
+            }
+        };
+        let sample = roll.roll(&mut Ctx::new(&mut rng, Some(&mut trace)));
+        sample.all_dice().map(|d| d.0).zip(first).collect()
+    }
+
+    #[test]
+    fn reroll_better_never_decreases() {
+        for seed in 0..50 {
+            for (val, first) in roll_with_first("10d6rb1", seed) {
+                assert!(val >= first, "{val} < {first}");
+            }
+            // rerolls all, but still never worse
+            for (val, first) in roll_with_first("10d6rb6", seed) {
+                assert!(val >= first, "{val} < {first}");
+            }
+        }
+    }
+
+    #[test]
+    fn reroll_once_keeps_new() {
+        let mut worse = false;
+        for seed in 0..50 {
+            for (val, first) in roll_with_first("10d6ro6", seed) {
+                worse |= val < first;
+            }
+            for (val, first) in roll_with_first("10d6ro2", seed) {
+                assert!(first <= 2 || val == first);
+            }
+        }
+        assert!(worse, "ro always rerolled to better values");
+    }
+
+    #[test_case("d[1-6, 10]" => vec![1, 2, 3, 4, 5, 6, 10] ; "range and face")]
+    #[test_case("3d[2,3,3,4,4,5]" => vec![2, 3, 3, 4, 4, 5] ; "repeated")]
+    #[test_case("d[0-1]" => vec![0, 1] ; "zero")]
+    #[test_case("d[]" => panics "failed to parse" ; "empty")]
+    #[test_case("d[6-1]" => panics "failed to parse" ; "bad range")]
+    #[test_case("d[1,,2]" => panics "failed to parse" ; "missing face")]
+    fn parse_faces(s: &str) -> Vec<u16> {
+        let roll = s.parse::<Roll>().expect("failed to parse");
+        roll.faces.unwrap().to_vec()
+    }
+
+    #[test_case("d[1-6, 10]" => "d[1-6,10]" ; "range")]
+    #[test_case("3d[2,3,3,4,4,5]+1" => "3d[2,3,3,4,4,5]+1" ; "repeated")]
+    #[test_case("d[1,2,4,5,6]" => "d[1,2,4-6]" ; "short runs")]
+    fn display_faces(s: &str) -> String {
+        let roll = s.parse::<Roll>().expect("failed to parse");
+        crate::ansi::strip(&roll.to_string())
+    }
+
+    #[test]
+    fn custom_faces() {
+        let faces = [2, 3, 4, 5];
+        for seed in 0..50 {
+            let sample = roll("3d[2,3,3,4,4,5]", seed);
+            assert_eq!(sample.dice.len(), 3);
+            assert!(sample.dice().all(|d| faces.contains(&d)));
+            assert_eq!(sample.total(), sample.dice().map(i64::from).sum::<i64>());
+        }
+        // explodes on the biggest face
+        assert_eq!(roll("2d[7]!x2", 0).dice.len(), 2 * 3);
+        // all the faces are the biggest, so they stop at the global limit
+        let max = MAX_EXPLOSIONS as usize;
+        assert_eq!(roll("d[5,5]!", 0).dice.len(), 1 + max);
+        assert_eq!(roll("d[5,5]!", 0).total(), 5 * (1 + max as i64));
+    }
+
+    #[test]
+    fn explode_cap() {
+        // a d1 always explodes
+        assert_eq!(roll("3d1!x2", 0).dice.len(), 3 * (1 + 2));
+        for seed in 0..100 {
+            assert!(roll("d2!x1", seed).dice.len() <= 2);
+        }
+
+        // always exploding without a cap stops at the global limit
+        let max = MAX_EXPLOSIONS as usize;
+        assert_eq!(roll("2d1!", 0).dice.len(), 2 * (1 + max));
+        assert_eq!(roll("d1!x5", 0).dice.len(), 1 + 5);
+        assert!("d6!x101".parse::<Roll>().is_err());
+
+        let roll = "4d6!x3".parse::<Roll>().unwrap();
+        assert_eq!(crate::ansi::strip(&roll.to_string()), "4d6!x3");
+    }
+
+    #[test]
+    fn modulo_range() {
+        for seed in 0..200 {
+            let total = roll("1d100 % 20", seed).total();
+            assert!((0..=19).contains(&total), "{total}");
+        }
+    }
+
+    #[test]
+    fn modulo_negative_total() {
+        for seed in 0..50 {
+            let total = roll("1d4-10 % 3", seed).total();
+            assert!((0..3).contains(&total), "{total}");
+        }
+    }
+
+    #[test]
+    fn exploded_successes() {
+        let mut exploded_success = false;
+        for seed in 0..50 {
+            let sample = roll("8d10>=7!", seed);
+            assert!(sample.roll.exploding);
+            let successes = sample.successes().unwrap();
+            let expected = sample.dice().filter(|&v| v >= 7).count() as u32;
+            assert_eq!(successes, expected);
+            assert_eq!(sample.total(), i64::from(successes));
+            // the extra dice are counted too
+            if sample.dice.len() > 8 && sample.dice[8..].iter().any(|d| d.val >= 7) {
+                exploded_success = true;
+            }
+        }
+        assert!(exploded_success);
+    }
+
+    #[test_case("8d10>=7!" => "8d10!>=7" ; "pool")]
+    #[test_case("6d6!x2>5+1" => "6d6!x2>5+1" ; "before")]
+    #[test_case("5d10<=3" => "5d10<=3" ; "roll under")]
+    #[test_case("4d6k3=6" => "4d6k3=6" ; "after select")]
+    #[test_case("3d6!>=5!" => panics "failed to parse" ; "exploding twice")]
+    #[test_case("3d6^>=5" => panics "failed to parse" ; "with reduce")]
+    fn parse_success(s: &str) -> String {
+        let roll = s.parse::<Roll>().expect("failed to parse");
+        crate::ansi::strip(&roll.to_string())
+    }
+
+    #[test]
+    fn reduce() {
+        for seed in 0..50 {
+            let sample = roll("3d6^+2", seed);
+            let max = sample.dice().max().unwrap() as i64;
+            assert_eq!(sample.total(), max + 2);
+
+            let sample = roll("3d6v", seed);
+            assert_eq!(sample.total(), sample.dice().min().unwrap() as i64);
+
+            // applied to the kept dice
+            let sample = roll("4d6d1v", seed);
+            assert_eq!(sample.total(), sample.dice().min().unwrap() as i64);
+        }
+        let text = crate::ansi::strip(&roll("3d1^+1", 0).to_string());
+        assert_eq!(text, "3d1^+1: [1,1,1]^+1 = 2");
+    }
+
+    #[test_case("3d6^" ; "max")]
+    #[test_case("3d6v-1" ; "min")]
+    #[test_case("4d6k2^" ; "after select")]
+    #[test_case("4d6k1v-1" ; "keep one")]
+    fn display_reduce(s: &str) {
+        let roll = s.parse::<Roll>().unwrap();
+        assert_eq!(crate::ansi::strip(&roll.to_string()), s);
+    }
+
+    #[test_case("2d1 div 2" => Num::Int(1) ; "exact")]
+    #[test_case("3d1 div 2" => Num::Float(1.5) ; "half")]
+    #[test_case("3d1+1div4" => Num::Int(1) ; "no spaces")]
+    #[test_case("10d1 % 7 div 4" => Num::Float(0.75) ; "after modulo")]
+    #[test_case("d6 div 0" => panics "failed to parse" ; "zero")]
+    #[test_case("3d6/2" => panics "failed to parse" ; "slash")]
+    fn division(s: &str) -> Num {
+        roll(s, 0).value()
+    }
+
+    #[test_case("2d6 div 2" ; "even dice")]
+    #[test_case("3d6 div 2" ; "odd dice")]
+    fn division_seeded(s: &str) {
+        let mut halves = 0;
+        for seed in 0..50 {
+            let sample = roll(s, seed);
+            let total = sample.total();
+            match sample.value() {
+                Num::Int(n) => assert_eq!(n * 2, total),
+                Num::Float(n) => {
+                    assert_eq!(n * 2.0, total as Float);
+                    assert_eq!(n.fract(), 0.5);
+                    halves += 1;
+                }
+            }
+        }
+        assert!(halves > 0);
+        let sample = roll(s, 0);
+        let text = crate::ansi::strip(&sample.to_string());
+        assert!(text.starts_with(&format!("{s}: [")), "{text}");
+        assert_eq!(format!("{sample:#}"), sample.fmt_value());
+    }
+
+    #[test]
+    fn division_in_query() {
+        let values = |q: &str| {
+            crate::run_query(q)
+                .unwrap()
+                .iter()
+                .map(|s| s.value())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values("3d1 div 2"), ["1.5"]);
+        assert_eq!(values("3d1 div 2, 2d1 div 2 / all o"), ["1.5", "1"]);
+        assert_eq!(values("/all o 4d1 div 2, 1d1"), ["2", "1"]);
+        assert_eq!(
+            values("3d1 div 2 floor, 3d1 div 2 ceil / all o"),
+            ["1", "2"]
+        );
+    }
+
+    #[test_case("3d1 div 2 floor" => Num::Int(1) ; "floor")]
+    #[test_case("3d1 div 2 ceil" => Num::Int(2) ; "ceil")]
+    #[test_case("3d1 div 2 round" => Num::Int(2) ; "round")]
+    #[test_case("3d1 div 2round" => Num::Int(2) ; "no space")]
+    #[test_case("3d1-6 div 4 floor" => Num::Int(-1) ; "negative floor")]
+    #[test_case("3d1-6 div 4 ceil" => Num::Int(0) ; "negative ceil")]
+    #[test_case("3d1-6 div 2 round" => Num::Int(-2) ; "negative round")]
+    #[test_case("3d1 floor" => panics "failed to parse" ; "without division")]
+    fn division_rounding(s: &str) -> Num {
+        roll(s, 0).value()
+    }
+
+    #[test]
+    fn division_rounding_seeded() {
+        for seed in 0..50 {
+            let exact = match roll("3d6 div 2", seed).value() {
+                Num::Int(n) => n as Float,
+                Num::Float(n) => n,
+            };
+            for (policy, expected) in [
+                ("floor", exact.floor()),
+                ("ceil", exact.ceil()),
+                ("round", exact.round()),
+            ] {
+                let sample = roll(&format!("3d6 div 2 {policy}"), seed);
+                assert_eq!(sample.value(), Num::Int(expected as i64), "{policy}");
+                let text = crate::ansi::strip(&sample.to_string());
+                assert!(
+                    text.starts_with(&format!("3d6 div 2 {policy}: [")),
+                    "{text}"
+                );
+            }
+        }
+    }
+
+    #[test_case("3d1+2 vs 5" => (5, 0, "success by 0".to_string()) ; "exact hit")]
+    #[test_case("3d1+2 vs 2" => (5, 3, "success by 3".to_string()) ; "success")]
+    #[test_case("3d1 vs 5" => (3, -2, "fail by 2".to_string()) ; "failure")]
+    #[test_case("3d1 vs -1" => (3, 4, "success by 4".to_string()) ; "negative target")]
+    #[test_case("1d1 vs -2147483648" => (1, 2147483649, "success by 2147483649".to_string()) ; "min target")]
+    fn versus(s: &str) -> (i64, i64, String) {
+        let sample = roll(s, 0);
+        let value = format!("{sample:#}");
+        let (total, outcome) = value.split_once(", ").unwrap();
+        assert_eq!(total, sample.total().to_string());
+        (
+            sample.total(),
+            sample.margin().unwrap(),
+            outcome.to_string(),
+        )
+    }
+
+    #[test]
+    fn versus_seeded() {
+        let (mut successes, mut fails) = (0, 0);
+        for seed in 0..50 {
+            let sample = roll("1d20+5 vs 15", seed);
+            let margin = sample.margin().unwrap();
+            assert_eq!(margin, sample.total() - 15);
+            let text = crate::ansi::strip(&sample.to_string());
+            assert!(text.starts_with("d20+5 vs 15: ["), "{text}");
+            if margin >= 0 {
+                assert!(text.ends_with(&format!("success by {margin}")), "{text}");
+                successes += 1;
+            } else {
+                assert!(text.ends_with(&format!("fail by {}", -margin)), "{text}");
+                fails += 1;
+            }
+        }
+        assert!(successes > 0 && fails > 0);
+        assert_eq!(roll("1d20", 0).margin(), None);
+        assert!("2d6 div 2 vs 3".parse::<Roll>().is_err());
+    }
+
+    #[test_case("1d20+2[bless]", "1d20+2" ; "single")]
+    #[test_case("1d20+2[bless]-1", "1d20+1" ; "with unlabeled")]
+    #[test_case("3d6 + 2 [bless] - 3 [ tired ] +10%", "3d6-1+10%" ; "spaces and percent")]
+    #[test_case("4d6k3+1[magic sword]", "4d6k3+1" ; "label with spaces")]
+    fn labeled_modifiers(labeled: &str, plain: &str) {
+        for seed in 0..10 {
+            let a = roll(labeled, seed);
+            let b = roll(plain, seed);
+            assert_eq!(a.total(), b.total());
+            assert_eq!(a.modifier(), b.modifier());
+        }
+    }
+
+    #[test]
+    fn labeled_modifiers_display() {
+        let parsed = "1d1+2[bless]-1[tired]+3".parse::<Roll>().unwrap();
+        assert_eq!(
+            crate::ansi::strip(&parsed.to_string()),
+            "d1+2[bless]-1[tired]+3"
+        );
+        let sample = roll("1d1 + 2[bless] - 1[ tired ]", 0);
+        assert_eq!(
+            crate::ansi::strip(&sample.to_string()),
+            "d1+2[bless]-1[tired]: [1]+2 (bless)-1 (tired) = 2"
+        );
+        let text = crate::ansi::strip(&format!("{}", Pretty(&sample)));
+        assert!(text.contains("  modifier: +2 (bless)\n  modifier: -1 (tired)\n"));
+        assert!("1d20+2[]".parse::<Roll>().is_err());
+    }
+
+    #[test_case("2 d 6 + 1", "2d6+1" ; "modifier")]
+    #[test_case(" 4d6 k 3 ", "4d6k3" ; "select")]
+    #[test_case("8 d10 >= 7 !", "8d10>=7!" ; "success")]
+    #[test_case("3 d [1, 2, 3] - 1", "3d[1,2,3]-1" ; "faces")]
+    #[test_case("10 d 1 + 10 % % 7", "10d1+10% % 7" ; "percent and modulo")]
+    #[test_case("1 d 20 + 5 vs 15", "1d20+5 vs 15" ; "versus")]
+    fn whitespace(spaced: &str, compact: &str) {
+        let a = spaced.parse::<Roll>().expect("failed to parse");
+        let b = compact.parse::<Roll>().expect("failed to parse");
+        assert_eq!(a, b);
+        assert_eq!(roll(spaced, 3), roll(compact, 3));
+    }
+
+    #[test_case("2 d 6 6" ; "split number")]
+    #[test_case("2 d 6 + " ; "dangling modifier")]
+    #[test_case("2 x 6" ; "not a die")]
+    fn whitespace_invalid(s: &str) {
+        assert!(s.parse::<Roll>().is_err());
+    }
+}
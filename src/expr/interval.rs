@@ -2,6 +2,7 @@
 
 use std::{
     fmt::{Display, Write},
+    rc::Rc,
     str::FromStr,
 };
 
@@ -10,15 +11,18 @@ use rand::{
     distributions::{Open01, OpenClosed01},
     Rng,
 };
+use rand_distr::{Distribution, Normal};
 
-use crate::{eval::Sample, Pcg};
+use super::dice::Roll;
+use crate::eval::Sample;
 use crate::{
-    eval::{Eval, EvalRes},
+    eval::{Ctx, Eval, EvalRes, ExprSample},
+    format::NumberFormat,
     regex,
 };
 
 /// Int type used in the interval
-pub type Int = i32;
+pub type Int = i64;
 /// Float type used in the interval
 pub type Float = f32;
 
@@ -28,12 +32,38 @@ pub struct Interval {
     low_inc: bool,
     high_inc: bool,
     kind: IntervalKind,
+    /// Sample from a normal distribution centered in the interval
+    ///
+    /// The standard deviation is a sixth of the width, and values outside
+    /// the interval are sampled again.
+    normal: bool,
+    /// Written after the interval, like `kg` in `[1..10]kg`
+    ///
+    /// Only added to the sampled value.
+    unit: Option<Rc<str>>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 enum IntervalKind {
-    Int(std::ops::Range<Int>),
+    /// Only the numbers `range.start + offset + k * step` are sampled
+    Int {
+        range: std::ops::Range<Int>,
+        step: Int,
+        offset: Int,
+    },
     Float(std::ops::Range<Float>),
+    /// Only the listed numbers are sampled, like `{1, 3, 5}`
+    Set(Rc<[Num]>),
+}
+
+impl IntervalKind {
+    fn int(range: std::ops::Range<Int>) -> Self {
+        Self::Int {
+            range,
+            step: 1,
+            offset: 0,
+        }
+    }
 }
 
 /// Error from [`Interval::from_str`]
@@ -58,14 +88,43 @@ impl FromStr for Interval {
     type Err = IntervalParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match parse_range(s) {
+        let s = normalize(s);
+        match parse_range(&s) {
             Err(IntervalParseError::NoMatch) => {}
             other => return other,
         }
-        parse_interval(s)
+        parse_interval(&s)
     }
 }
 
+/// Rewrites the alternative notations to the usual one
+///
+/// A leading `in` is removed, like `in [1..10]`, and comparisons like
+/// `1 <= x < 10` or `10 ≥ x > 1` become `[1..10)`.
+fn normalize(s: &str) -> std::borrow::Cow<'_, str> {
+    if let Some(rest) = s
+        .strip_prefix("in")
+        .filter(|r| r.starts_with(char::is_whitespace))
+    {
+        return rest.trim_start().into();
+    }
+    let re = regex!(r"\A(\S+?)\s*(<=|≤|<|>=|≥|>)\s*[a-z]\s*(<=|≤|<|>=|≥|>)\s*(\S+)\z");
+    let Some(caps) = re.captures(s) else {
+        return s.into();
+    };
+    let inclusive = |op: &str| !matches!(op, "<" | ">");
+    let ascending = |op: &str| op.starts_with(['<', '≤']);
+    let (first, op1, op2, last) = (&caps[1], &caps[2], &caps[3], &caps[4]);
+    let (low, low_op, high_op, high) = match (ascending(op1), ascending(op2)) {
+        (true, true) => (first, op1, op2, last),
+        (false, false) => (last, op2, op1, first),
+        _ => return s.into(),
+    };
+    let open = if inclusive(low_op) { '[' } else { '(' };
+    let close = if inclusive(high_op) { ']' } else { ')' };
+    format!("{open}{low}..{high}{close}").into()
+}
+
 const START: &str = "start";
 const END: &str = "end";
 const TOO_BIG: &str = "value is too big";
@@ -106,18 +165,25 @@ fn build_int_range(
 
 fn parse_interval(s: &str) -> Result<Interval, IntervalParseError> {
     let re = regex!(
-        r"\A([\[\(])\s*((?:\+|-)?(?:\d*\.)?\d+)\s*(,|\.{2})\s*((?:\+|-)?(?:\d*\.)?\d+)\s*([\]\)])\z"
+        r"\A([\[\(])\s*((?:\+|-)?(?:\d*\.)?\d+)\s*(,|\.{2})\s*((?:\+|-)?(?:\d*\.)?\d+)(?:\s+step\s+(\d+)(?:\s+offset\s+(\d+))?)?(\s+normal)?\s*([\]\)])([\p{L}°%][\p{L}°%\d]*)?\z"
     );
 
     let caps = re.captures(s).ok_or(IntervalParseError::NoMatch)?;
 
     let low_inc = &caps[1] == "[";
-    let high_inc = &caps[5] == "]";
+    let high_inc = &caps[8] == "]";
+    let normal = caps.get(7).is_some();
+    let unit = caps.get(9).map(|m| Rc::from(m.as_str()));
     let start = &caps[2];
     let end = &caps[4];
     let is_float = &caps[3] == "," || start.contains('.') || end.contains('.');
 
     let kind = if is_float {
+        if caps.get(5).is_some() {
+            return Err(IntervalParseError::Invalid(
+                "step is only for integer intervals".to_string(),
+            ));
+        }
         let start = parse_float(start, START)?;
         let end = parse_float(end, END)?;
         let range = start..end;
@@ -129,15 +195,54 @@ fn parse_interval(s: &str) -> Result<Interval, IntervalParseError> {
         let start = parse_int(start, START)?;
         let end = parse_int(end, END)?;
         let range = build_int_range(start, end, low_inc, high_inc)?;
-        IntervalKind::Int(range)
+        let step = caps
+            .get(5)
+            .map_or(Ok(1), |m| parse_int(m.as_str(), "step"))?;
+        let offset = caps
+            .get(6)
+            .map_or(Ok(0), |m| parse_int(m.as_str(), "offset"))?;
+        if step == 0 {
+            return Err(IntervalParseError::Invalid("step can't be 0".to_string()));
+        }
+        if offset >= step {
+            return Err(IntervalParseError::Invalid(
+                "offset must be less than the step".to_string(),
+            ));
+        }
+        if first_step(&range, low_inc, step, offset).is_none() {
+            return Err(IntervalParseError::Invalid(EMPTY_INTERVAL.to_string()));
+        }
+        IntervalKind::Int {
+            range,
+            step,
+            offset,
+        }
     };
     Ok(Interval {
         low_inc,
         high_inc,
         kind,
+        normal,
+        unit,
     })
 }
 
+/// First number of a stepped int range
+///
+/// Steps start at the bound as written, so `(0..20 step 5)` starts at 5.
+fn first_step(range: &std::ops::Range<Int>, low_inc: bool, step: Int, offset: Int) -> Option<Int> {
+    let written = if low_inc {
+        range.start
+    } else {
+        range.start - 1
+    };
+    let mut first = written.checked_add(offset)?;
+    if first < range.start {
+        first = first.checked_add(step)?;
+    }
+    (first < range.end).then_some(first)
+}
+
 fn parse_range(s: &str) -> Result<Interval, IntervalParseError> {
     let re = regex!(r"\A((?:\+|-)?\d+)..(=)?((?:\+|-)?\d+)\z");
 
@@ -152,40 +257,276 @@ fn parse_range(s: &str) -> Result<Interval, IntervalParseError> {
     Ok(Interval {
         low_inc: true,
         high_inc: inclusive,
-        kind: IntervalKind::Int(range),
+        kind: IntervalKind::int(range),
+        normal: false,
+        unit: None,
     })
 }
 
+/// Integer interval with dice rolls as bounds
+///
+/// The bounds are rolled each time it is sampled, like `[1d4..1d4+10]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynInterval {
+    low_inc: bool,
+    high_inc: bool,
+    start: Bound,
+    end: Bound,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Bound {
+    Int(Int),
+    Roll(Roll),
+}
+
+/// Times the bounds of a [`DynInterval`] are rolled again if the interval is
+/// empty
+const MAX_RESAMPLES: usize = 100;
+
+impl FromStr for DynInterval {
+    type Err = IntervalParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = regex!(r"\A([\[\(])\s*(.+?)\s*\.\.\s*(.+?)\s*([\]\)])\z");
+
+        let caps = re.captures(s).ok_or(IntervalParseError::NoMatch)?;
+
+        let parse_bound = |s: &str| {
+            if let Ok(n) = s.parse::<Int>() {
+                return Some(Bound::Int(n));
+            }
+            // the margin of a `vs` target would be lost
+            s.parse::<Roll>()
+                .ok()
+                .filter(|r| r.is_integer() && !r.has_target())
+                .map(Bound::Roll)
+        };
+        let (Some(start), Some(end)) = (parse_bound(&caps[2]), parse_bound(&caps[3])) else {
+            return Err(IntervalParseError::NoMatch);
+        };
+        if let (Bound::Int(_), Bound::Int(_)) = (&start, &end) {
+            // not dynamic, and not a valid static interval
+            return Err(IntervalParseError::NoMatch);
+        }
+
+        Ok(DynInterval {
+            low_inc: &caps[1] == "[",
+            high_inc: &caps[4] == "]",
+            start,
+            end,
+        })
+    }
+}
+
+impl Bound {
+    fn resolve(&self, ctx: &mut Ctx) -> Int {
+        match self {
+            Bound::Int(n) => *n,
+            Bound::Roll(roll) => match roll.roll(ctx).value() {
+                Num::Int(n) => n,
+                Num::Float(_) => unreachable!("only integer rolls are bounds"),
+            },
+        }
+    }
+}
+
+impl Eval for DynInterval {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        let mut bounds = (0, 0);
+        for _ in 0..MAX_RESAMPLES {
+            let start = self.start.resolve(ctx);
+            let end = self.end.resolve(ctx);
+            if let Ok(range) = build_int_range(start, end, self.low_inc, self.high_inc) {
+                let interval = Interval {
+                    low_inc: self.low_inc,
+                    high_inc: self.high_inc,
+                    kind: IntervalKind::int(range),
+                    normal: false,
+                    unit: None,
+                };
+                return interval.eval(ctx);
+            }
+            ctx.trace(|| format!("{self}: empty with bounds {start} and {end}"));
+            bounds = (start, end);
+        }
+
+        // give up and use the last bounds, both included
+        let (start, end) = (bounds.0.min(bounds.1), bounds.0.max(bounds.1));
+        let range = match build_int_range(start, end, true, true) {
+            Ok(range) => range,
+            Err(err) => {
+                // the bounds can be too big, like an end of `i64::MAX`
+                ctx.fail(crate::Error::Expr(format!("{self}: {err}")));
+                return EvalRes::Emtpy;
+            }
+        };
+        let interval = Interval {
+            low_inc: true,
+            high_inc: true,
+            kind: IntervalKind::int(range),
+            normal: false,
+            unit: None,
+        };
+        interval.eval(ctx)
+    }
+}
+
+impl Display for DynInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_char(if self.low_inc { '[' } else { '(' })?;
+        write!(f, "{}..{}", self.start, self.end)?;
+        f.write_char(if self.high_inc { ']' } else { ')' })
+    }
+}
+
+impl Display for Bound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bound::Int(n) => n.fmt(f),
+            Bound::Roll(roll) => roll.fmt(f),
+        }
+    }
+}
+
+impl Interval {
+    /// Interval of only the given numbers, all with the same probability
+    ///
+    /// It's written like a subquery of only numbers, `{1, 3, 5}`, so the query
+    /// parser builds it. `None` with less than 2 numbers.
+    pub fn set(values: Vec<Num>) -> Option<Self> {
+        if values.len() < 2 {
+            return None;
+        }
+        Some(Interval {
+            low_inc: true,
+            high_inc: true,
+            kind: IntervalKind::Set(values.into()),
+            normal: false,
+            unit: None,
+        })
+    }
+}
+
+impl Interval {
+    /// Numeric span between the bounds
+    ///
+    /// The bounds are taken as written, even if they are not included, so
+    /// `(1..10)` and `[1..10]` both have a width of `9`. A set goes from its
+    /// smallest to its biggest number.
+    pub fn width(&self) -> Num {
+        match self.bounds() {
+            (Num::Int(start), Num::Int(end)) => match end.checked_sub(start) {
+                Some(w) => Num::Int(w),
+                None => Num::Float(end as Float - start as Float),
+            },
+            (start, end) => Num::Float(end.as_float() - start.as_float()),
+        }
+    }
+
+    /// Middle point between the bounds
+    ///
+    /// Like [`Interval::width`], it uses the bounds as written. For integer
+    /// intervals it's only a [`Num::Float`] if it is not a whole number.
+    pub fn midpoint(&self) -> Num {
+        match self.bounds() {
+            (Num::Int(start), Num::Int(end)) => {
+                let sum = start as i128 + end as i128;
+                if sum % 2 == 0 {
+                    Num::Int((sum / 2) as Int)
+                } else {
+                    Num::Float(sum as Float / 2.0)
+                }
+            }
+            (start, end) => {
+                let (start, end) = (start.as_float(), end.as_float());
+                Num::Float(start + (end - start) / 2.0)
+            }
+        }
+    }
+
+    /// Bounds as written, or the smallest and biggest numbers of a set
+    fn bounds(&self) -> (Num, Num) {
+        match &self.kind {
+            IntervalKind::Int { .. } => {
+                let (start, end) = self.int_bounds();
+                (Num::Int(start), Num::Int(end))
+            }
+            IntervalKind::Float(r) => (Num::Float(r.start), Num::Float(r.end)),
+            IntervalKind::Set(values) => {
+                let by_value = |a: &&Num, b: &&Num| a.as_float().total_cmp(&b.as_float());
+                let min = values.iter().min_by(by_value).expect("not empty set");
+                let max = values.iter().max_by(by_value).expect("not empty set");
+                (*min, *max)
+            }
+        }
+    }
+
+    /// Bounds of an int interval as written
+    fn int_bounds(&self) -> (Int, Int) {
+        let IntervalKind::Int { range: r, .. } = &self.kind else {
+            panic!("not an int interval")
+        };
+        let mut start = r.start;
+        if !self.low_inc {
+            start -= 1; // checked in creation
+        }
+        let mut end = r.end;
+        if self.high_inc {
+            end -= 1; // checked in creation
+        }
+        (start, end)
+    }
+}
+
 impl Display for Interval {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let IntervalKind::Set(values) = &self.kind {
+            f.write_char('{')?;
+            for (i, n) in values.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                n.fmt(f)?;
+            }
+            return f.write_char('}');
+        }
+
         match self.low_inc {
             true => f.write_char('[')?,
             false => f.write_char('(')?,
         }
 
         match &self.kind {
-            IntervalKind::Int(r) => {
-                let mut start = r.start;
-                if !self.low_inc {
-                    start = start.checked_sub(1).unwrap(); // checked in creation
+            IntervalKind::Int { step, offset, .. } => {
+                let (start, end) = self.int_bounds();
+                write!(f, "{start}..{end}")?;
+                if *step != 1 {
+                    write!(f, " step {step}")?;
                 }
-                let mut end = r.end;
-                if self.high_inc {
-                    end = end.checked_sub(1).unwrap(); // checked in creation
+                if *offset != 0 {
+                    write!(f, " offset {offset}")?;
                 }
-                write!(f, "{start}..{end}")?;
             }
             IntervalKind::Float(r) => {
                 let start = r.start;
                 let end = r.end;
                 write!(f, "{start}, {end}")?;
             }
+            IntervalKind::Set(_) => unreachable!("written before"),
+        }
+        if self.normal {
+            f.write_str(" normal")?;
         }
 
         match self.high_inc {
-            true => f.write_char(']'),
-            false => f.write_char(')'),
+            true => f.write_char(']')?,
+            false => f.write_char(')')?,
         }
+        if let Some(unit) = &self.unit {
+            f.write_str(unit)?;
+        }
+        Ok(())
     }
 }
 
@@ -199,24 +540,68 @@ struct IntervalSample {
     interval: Interval,
     /// Value obtained
     value: Num,
+    /// Format of the value
+    fmt: NumberFormat,
 }
 
 /// Either an [`Int`] or a [`Float`].
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum Num {
+pub enum Num {
     Int(Int),
     Float(Float),
 }
 
 impl Eval for Interval {
-    fn eval(&self, rng: &mut Pcg) -> EvalRes {
+    fn eval(&self, ctx: &mut Ctx) -> EvalRes {
+        let rng = &mut *ctx.rng;
         let Interval {
             low_inc,
             high_inc,
             kind,
+            normal,
+            ..
         } = &self;
         let value = match kind {
-            IntervalKind::Int(r) => Num::Int(rng.gen_range(r.clone())),
+            IntervalKind::Int {
+                range,
+                step,
+                offset,
+            } if *normal => {
+                let first =
+                    first_step(range, *low_inc, *step, *offset).expect("checked in creation");
+                let count = (range.end as i128 - 1 - first as i128) / *step as i128 + 1;
+                // each index takes the same width around its position
+                let (low, high) = (-0.5, count as f64 - 0.5);
+                let x = truncated_normal(rng, low, high, |x| low <= x && x < high);
+                let k = ((x + 0.5).floor() as i128).clamp(0, count - 1);
+                Num::Int((first as i128 + k * *step as i128) as Int)
+            }
+            IntervalKind::Float(r) if *normal => {
+                let inside = |x: f64| {
+                    let x = x as Float;
+                    let low = if *low_inc { x >= r.start } else { x > r.start };
+                    let high = if *high_inc { x <= r.end } else { x < r.end };
+                    low && high
+                };
+                let x = truncated_normal(rng, r.start as f64, r.end as f64, inside);
+                Num::Float(x as Float)
+            }
+            IntervalKind::Int {
+                range,
+                step: 1,
+                offset: 0,
+            } => Num::Int(rng.gen_range(range.clone())),
+            IntervalKind::Int {
+                range,
+                step,
+                offset,
+            } => {
+                let first =
+                    first_step(range, *low_inc, *step, *offset).expect("checked in creation");
+                // fits back in an Int because step > 1
+                let count = ((range.end as i128 - 1 - first as i128) / *step as i128 + 1) as Int;
+                Num::Int(first + rng.gen_range(0..count) * step)
+            }
             IntervalKind::Float(r) => {
                 let f = match (low_inc, high_inc) {
                     (true, true) => rng.gen_range(r.start..=r.end),
@@ -234,21 +619,95 @@ impl Eval for Interval {
                 };
                 Num::Float(f)
             }
+            IntervalKind::Set(values) => values[rng.gen_range(0..values.len())],
         };
+        ctx.trace(|| format!("{self}: {value}"));
         Sample::expr(Box::new(IntervalSample {
             value,
+            fmt: ctx.fmt,
             interval: self.clone(),
         }))
         .into()
     }
 }
 
+/// Times a normal interval is sampled again if the value is outside
+const MAX_NORMAL_RESAMPLES: usize = 100;
+
+/// Samples a normal distribution centered between `low` and `high`
+///
+/// The standard deviation is a sixth of the width, so almost all values are
+/// already `inside`. The rest are sampled again, up to a limit, and then the
+/// center is used.
+fn truncated_normal(rng: &mut impl Rng, low: f64, high: f64, inside: impl Fn(f64) -> bool) -> f64 {
+    let mean = low + (high - low) / 2.0;
+    let normal = Normal::new(mean, (high - low) / 6.0).expect("finite std dev");
+    for _ in 0..MAX_NORMAL_RESAMPLES {
+        let x = normal.sample(rng);
+        if inside(x) {
+            return x;
+        }
+    }
+    mean
+}
+
+impl ExprSample for IntervalSample {
+    fn kind(&self) -> &'static str {
+        "interval"
+    }
+
+    fn expression(&self) -> String {
+        self.interval.to_string()
+    }
+
+    fn number(&self) -> Option<Num> {
+        Some(self.value)
+    }
+}
+
 impl Display for IntervalSample {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut value = match self.value {
+            Num::Int(n) => self.fmt.int(n),
+            Num::Float(n) => self.fmt.float(n),
+        };
+        if let Some(unit) = &self.interval.unit {
+            value.push_str(unit);
+        }
         if f.alternate() {
-            self.value.fmt(f)
+            f.pad(&value)
+        } else {
+            write!(f, "{}: {value}", self.interval.bold().yellow())
+        }
+    }
+}
+
+impl FromStr for Num {
+    type Err = IntervalParseError;
+
+    /// Parses a number like the bounds of an interval
+    ///
+    /// It's a [`Num::Float`] only if it has a decimal point, like `3.5` or
+    /// `.5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = regex!(r"\A[+-]?(?:\d*\.)?\d+\z");
+        if !re.is_match(s) {
+            return Err(IntervalParseError::NoMatch);
+        }
+        if s.contains('.') {
+            parse_float(s, "number").map(Num::Float)
         } else {
-            write!(f, "{}: {}", self.interval.bold().yellow(), self.value)
+            parse_int(s, "number").map(Num::Int)
+        }
+    }
+}
+
+impl Num {
+    /// The number as a float, even if it's an int
+    pub(crate) fn as_float(self) -> Float {
+        match self {
+            Num::Int(n) => n as Float,
+            Num::Float(n) => n,
         }
     }
 }
@@ -280,8 +739,8 @@ mod tests {
     fn parse_int(s: &str) -> std::ops::Range<Int> {
         let interval = s.parse::<Interval>().expect("failed to parse");
         match interval.kind {
-            IntervalKind::Int(r) => r,
-            IntervalKind::Float(_) => panic!("not int"),
+            IntervalKind::Int { range: r, .. } => r,
+            _ => panic!("not int"),
         }
     }
 
@@ -304,7 +763,7 @@ mod tests {
     fn parse_float(s: &str) -> (std::ops::Range<Float>, bool, bool) {
         let interval = s.parse::<Interval>().expect("failed to parse");
         match interval.kind {
-            IntervalKind::Int(_) => panic!("not float"),
+            IntervalKind::Int { .. } | IntervalKind::Set(_) => panic!("not float"),
             IntervalKind::Float(r) => (r, interval.low_inc, interval.high_inc),
         }
     }
@@ -313,7 +772,7 @@ mod tests {
         fn start(&self) -> (Num, bool) {
             let inc = self.low_inc;
             let n = match &self.kind {
-                IntervalKind::Int(r) => {
+                IntervalKind::Int { range: r, .. } => {
                     let mut start = r.start;
                     if !inc {
                         start -= 1;
@@ -321,6 +780,7 @@ mod tests {
                     Num::Int(start)
                 }
                 IntervalKind::Float(r) => Num::Float(r.start),
+                IntervalKind::Set(_) => panic!("not a range"),
             };
             (n, inc)
         }
@@ -328,7 +788,7 @@ mod tests {
         fn end(&self) -> (Num, bool) {
             let inc = self.high_inc;
             let n = match &self.kind {
-                IntervalKind::Int(r) => {
+                IntervalKind::Int { range: r, .. } => {
                     let mut end = r.end;
                     if inc {
                         end -= 1;
@@ -336,11 +796,249 @@ mod tests {
                     Num::Int(end)
                 }
                 IntervalKind::Float(r) => Num::Float(r.end),
+                IntervalKind::Set(_) => panic!("not a range"),
             };
             (n, inc)
         }
     }
 
+    #[test_case("[0..10000000000]" => 0..10_000_000_001 ; "wide")]
+    #[test_case("-3000000000..=3000000000" => -3_000_000_000..3_000_000_001 ; "wide neg")]
+    #[test_case("[0..9223372036854775807)" => 0..Int::MAX ; "max")]
+    #[test_case("[0..9223372036854775807]" => panics "failed to parse" ; "too big")]
+    fn parse_wide(s: &str) -> std::ops::Range<Int> {
+        parse_int(s)
+    }
+
+    #[test]
+    fn sample_wide() {
+        let interval = "[5000000000..10000000000]".parse::<Interval>().unwrap();
+        for seed in 0..20 {
+            let mut rng = crate::rng::Generator::new(crate::rng::Algorithm::Pcg, Some(seed));
+            let res = interval.eval(&mut Ctx::new(&mut rng, None));
+            let EvalRes::Single(sample) = res else {
+                panic!("not a single sample")
+            };
+            let value = sample.value().parse::<Int>().unwrap();
+            assert!((5_000_000_000..=10_000_000_000).contains(&value), "{value}");
+        }
+    }
+
+    fn sample_values(expr: &impl Eval, seeds: std::ops::Range<u64>) -> Vec<Int> {
+        seeds
+            .map(|seed| {
+                let mut rng = crate::rng::Generator::new(crate::rng::Algorithm::Pcg, Some(seed));
+                let EvalRes::Single(sample) = expr.eval(&mut Ctx::new(&mut rng, None)) else {
+                    panic!("not a single sample")
+                };
+                sample.value().parse::<Int>().unwrap()
+            })
+            .collect()
+    }
+
+    #[test_case("[0..20 step 5]" => vec![0, 5, 10, 15, 20] ; "step")]
+    #[test_case("[0..20 step 5 offset 2]" => vec![2, 7, 12, 17] ; "offset")]
+    #[test_case("(0..20) step 5]" => panics "failed to parse" ; "bad syntax")]
+    #[test_case("(0..20 step 5)" => vec![5, 10, 15] ; "exclusive")]
+    #[test_case("[-7..7 step 7]" => vec![-7, 0, 7] ; "negative")]
+    #[test_case("[1..3 step 10]" => vec![1] ; "single")]
+    #[test_case("[1..3 step 10 offset 5]" => panics "failed to parse" ; "empty")]
+    #[test_case("[0..20 step 5 offset 5]" => panics "failed to parse" ; "offset too big")]
+    #[test_case("[0..20 step 0]" => panics "failed to parse" ; "zero step")]
+    #[test_case("[0, 20 step 5]" => panics "failed to parse" ; "float")]
+    fn step_values(s: &str) -> Vec<Int> {
+        let interval = s.parse::<Interval>().expect("failed to parse");
+        let mut values = sample_values(&interval, 0..200);
+        values.sort();
+        values.dedup();
+        values
+    }
+
+    #[test_case("3" => Num::Int(3) ; "int")]
+    #[test_case("+3" => Num::Int(3) ; "plus sign")]
+    #[test_case("-42" => Num::Int(-42) ; "negative int")]
+    #[test_case("3.5" => Num::Float(3.5) ; "float")]
+    #[test_case("-.5" => Num::Float(-0.5) ; "negative short float")]
+    #[test_case("3.0" => Num::Float(3.0) ; "whole float")]
+    fn parse_num(s: &str) -> Num {
+        s.parse().expect("failed to parse")
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("3." ; "trailing point")]
+    #[test_case("3x" ; "junk")]
+    #[test_case(" 3" ; "space")]
+    #[test_case("--3" ; "double sign")]
+    #[test_case("1e5" ; "exponent")]
+    #[test_case("inf" ; "infinity")]
+    #[test_case("99999999999999999999" ; "too big")]
+    fn parse_num_invalid(s: &str) {
+        assert!(s.parse::<Num>().is_err());
+    }
+
+    #[test_case("in [1..10]", "[1..10]" ; "in prefix")]
+    #[test_case("in   (0, 1)m", "(0, 1)m" ; "in with unit")]
+    #[test_case("in 1..=5", "1..=5" ; "in range")]
+    #[test_case("1 <= x <= 10", "[1..10]" ; "ascii inclusive")]
+    #[test_case("1 ≤ x < 10", "[1..10)" ; "unicode")]
+    #[test_case("10≥n>-1", "(-1..10]" ; "descending")]
+    #[test_case("0.5 < x ≤ 1", "(0.5..1]" ; "float")]
+    fn alternative_notation(alt: &str, usual: &str) {
+        let a = alt.parse::<Interval>().expect("failed to parse");
+        let b = usual.parse::<Interval>().expect("failed to parse");
+        assert_eq!(a, b);
+        assert_eq!(a.to_string(), b.to_string());
+        for seed in 0..10 {
+            let sample = |i: &Interval| {
+                let mut rng = crate::rng::Generator::new(crate::rng::Algorithm::Pcg, Some(seed));
+                let EvalRes::Single(sample) = i.eval(&mut Ctx::new(&mut rng, None)) else {
+                    panic!("not a single sample")
+                };
+                sample.to_string()
+            };
+            assert_eq!(sample(&a), sample(&b));
+        }
+    }
+
+    #[test_case("in[1..10]" ; "no space after in")]
+    #[test_case("1 < x > 10" ; "mixed directions")]
+    #[test_case("1 < xy < 10" ; "long name")]
+    #[test_case("in" ; "only in")]
+    fn alternative_notation_invalid(s: &str) {
+        assert!(s.parse::<Interval>().is_err());
+    }
+
+    #[test_case("[1..10]kg" => "kg" ; "int")]
+    #[test_case("[0, 1)m" => "m" ; "float")]
+    #[test_case("(0..40 step 5]°C" => "°C" ; "step")]
+    #[test_case("[0..100]%" => "%" ; "percent")]
+    #[test_case("[1..10] kg" => panics "failed to parse" ; "space")]
+    fn unit(s: &str) -> String {
+        let interval = s.parse::<Interval>().expect("failed to parse");
+        assert_eq!(interval.to_string(), s);
+        let mut rng = crate::rng::Generator::new(crate::rng::Algorithm::Pcg, Some(0));
+        let EvalRes::Single(sample) = interval.eval(&mut Ctx::new(&mut rng, None)) else {
+            panic!("not a single sample")
+        };
+        let value = sample.value();
+        let unit = value.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+        assert!(value.len() > unit.len(), "{value}");
+        assert!(sample.to_string().ends_with(&value));
+        unit.to_string()
+    }
+
+    #[test]
+    fn different_units() {
+        let res = crate::run_query("[1..10]kg, [1..10]lb, [1..10] / all o").unwrap();
+        let values = res.iter().map(|s| s.value()).collect::<Vec<_>>();
+        assert!(values[0].ends_with("kg"), "{values:?}");
+        assert!(values[1].ends_with("lb"), "{values:?}");
+        assert!(values[2].parse::<Int>().is_ok(), "{values:?}");
+    }
+
+    #[test_case("[0..100 normal]", 0, 100 ; "int")]
+    #[test_case("(0..100) normal", 0, 100 => panics "failed to parse" ; "bad syntax")]
+    #[test_case("(0..10 normal)", 1, 9 ; "exclusive")]
+    #[test_case("[0..1000 step 10 normal]", 0, 1000 ; "step")]
+    fn normal_bounds(s: &str, low: Int, high: Int) {
+        let interval = s.parse::<Interval>().expect("failed to parse");
+        assert!(interval.normal);
+        assert_eq!(interval.to_string(), s);
+        let values = sample_values(&interval, 0..500);
+        assert!(values.iter().all(|v| (low..=high).contains(v)));
+        let width = (high - low) as f64;
+        let center = low as f64 + width / 2.0;
+        // about 68% should be in one standard deviation
+        let near = values
+            .iter()
+            .filter(|&&v| (v as f64 - center).abs() <= width / 6.0)
+            .count();
+        assert!(near > 300, "{near} of 500 near the center");
+        let mean = values.iter().sum::<Int>() as f64 / values.len() as f64;
+        assert!((mean - center).abs() < width / 20.0, "mean {mean}");
+    }
+
+    #[test]
+    fn normal_float() {
+        let interval = "(0, 1) normal]".parse::<Interval>();
+        assert!(interval.is_err());
+        let interval = "(0, 1 normal)".parse::<Interval>().unwrap();
+        assert_eq!(interval.to_string(), "(0, 1 normal)");
+        let mut near = 0;
+        for seed in 0..500 {
+            let mut rng = crate::rng::Generator::new(crate::rng::Algorithm::Pcg, Some(seed));
+            let EvalRes::Single(sample) = interval.eval(&mut Ctx::new(&mut rng, None)) else {
+                panic!("not a single sample")
+            };
+            let value = sample.value().parse::<Float>().unwrap();
+            assert!(0.0 < value && value < 1.0, "{value}");
+            if (value - 0.5).abs() <= 1.0 / 6.0 {
+                near += 1;
+            }
+        }
+        assert!(near > 300, "{near} of 500 near the center");
+    }
+
+    #[test]
+    fn step_display() {
+        let interval = "[0..100 step 5 offset 2]".parse::<Interval>().unwrap();
+        assert_eq!(interval.to_string(), "[0..100 step 5 offset 2]");
+        let interval = "(0..100 step 5)".parse::<Interval>().unwrap();
+        assert_eq!(interval.to_string(), "(0..100 step 5)");
+    }
+
+    #[test_case("[1d4..1d4+10]" ; "both dice")]
+    #[test_case("(1d6..20]" ; "start dice")]
+    #[test_case("[0..2d6!]" ; "end dice")]
+    #[test_case("[1..5]" => panics "failed to parse" ; "not dynamic")]
+    #[test_case("[a..1d6]" => panics "failed to parse" ; "bad bound")]
+    #[test_case("[1..3d6 div 2 ceil]" ; "rounded division")]
+    #[test_case("[1..3d6 div 2]" => panics "failed to parse" ; "division")]
+    #[test_case("[1..1d20 vs 5]" => panics "failed to parse" ; "versus")]
+    fn parse_dynamic(s: &str) {
+        s.parse::<DynInterval>().expect("failed to parse");
+    }
+
+    #[test]
+    fn dynamic_bounds() {
+        let interval = "[1d4..1d4+10]".parse::<DynInterval>().unwrap();
+        for value in sample_values(&interval, 0..100) {
+            assert!((1..=14).contains(&value), "{value}");
+        }
+        let interval = "[0..4d1 div 3 floor]".parse::<DynInterval>().unwrap();
+        for value in sample_values(&interval, 0..20) {
+            assert!((0..=1).contains(&value), "{value}");
+        }
+    }
+
+    #[test]
+    fn dynamic_bounds_empty() {
+        // many times the start is bigger than the end, or equal while exclusive
+        let interval = "[1d20..5]".parse::<DynInterval>().unwrap();
+        for value in sample_values(&interval, 0..100) {
+            assert!((1..=20).contains(&value), "{value}");
+        }
+        let interval = "(1d6..1d6)".parse::<DynInterval>().unwrap();
+        for value in sample_values(&interval, 0..100) {
+            assert!((2..=5).contains(&value), "{value}");
+        }
+        // always empty, gives up
+        let interval = "(1d1..2)".parse::<DynInterval>().unwrap();
+        for value in sample_values(&interval, 0..10) {
+            assert!((1..=2).contains(&value), "{value}");
+        }
+    }
+
+    #[test]
+    fn dynamic_bounds_too_big() {
+        let interval = "[1d4..9223372036854775807]".parse::<DynInterval>().unwrap();
+        let mut rng = crate::rng::Generator::new(crate::rng::Algorithm::Pcg, Some(0));
+        let mut ctx = Ctx::new(&mut rng, None);
+        assert!(matches!(interval.eval(&mut ctx), EvalRes::Emtpy));
+        let err = ctx.take_error().expect("no error");
+        assert!(err.to_string().contains("too big"), "{err}");
+    }
+
     #[test_case("1..5" => (Num::Int(1), true) ; "range")]
     #[test_case("[1..5]" => (Num::Int(1), true) ; "inclusive")]
     #[test_case("(1..5]" => (Num::Int(1), false) ; "exclusive")]
@@ -349,6 +1047,25 @@ mod tests {
         interval.start()
     }
 
+    #[test_case("[1..10]" => Num::Int(9) ; "int inclusive")]
+    #[test_case("(1..10)" => Num::Int(9) ; "int exclusive")]
+    #[test_case("1..=10" => Num::Int(9) ; "int range")]
+    #[test_case("[-5..5)" => Num::Int(10) ; "int neg")]
+    #[test_case("[1, 5]" => Num::Float(4.0) ; "float inclusive")]
+    #[test_case("(0.5, 1)" => Num::Float(0.5) ; "float exclusive")]
+    fn width(s: &str) -> Num {
+        s.parse::<Interval>().expect("failed to parse").width()
+    }
+
+    #[test_case("[1..9]" => Num::Int(5) ; "int whole")]
+    #[test_case("[1..10]" => Num::Float(5.5) ; "int half")]
+    #[test_case("(-4..4)" => Num::Int(0) ; "int exclusive")]
+    #[test_case("[1, 2]" => Num::Float(1.5) ; "float")]
+    #[test_case("(-3, -1)" => Num::Float(-2.0) ; "float neg")]
+    fn midpoint(s: &str) -> Num {
+        s.parse::<Interval>().expect("failed to parse").midpoint()
+    }
+
     #[test_case("1..5" => (Num::Int(5), false) ; "range exclusive")]
     #[test_case("1..=5" => (Num::Int(5), true) ; "range inclusive")]
     #[test_case("[1..5]" => (Num::Int(5), true) ; "inclusive")]
@@ -357,4 +1074,23 @@ mod tests {
         let interval = s.parse::<Interval>().expect("failed to parse");
         interval.end()
     }
+
+    #[test]
+    fn set() {
+        let set = Interval::set(vec![Num::Int(1), Num::Int(3), Num::Int(5)]).unwrap();
+        assert_eq!(set.to_string(), "{1, 3, 5}");
+        assert_eq!(set.width(), Num::Int(4));
+        assert_eq!(set.midpoint(), Num::Int(3));
+
+        let values = sample_values(&set, 0..300);
+        for n in [1, 3, 5] {
+            let count = values.iter().filter(|&&v| v == n).count();
+            assert!(count.abs_diff(100) < 30, "{n} sampled {count} times");
+        }
+        assert!(values.iter().all(|v| [1, 3, 5].contains(v)));
+
+        let mixed = Interval::set(vec![Num::Int(1), Num::Float(2.5)]).unwrap();
+        assert_eq!(mixed.width(), Num::Float(1.5));
+        assert!(Interval::set(vec![Num::Int(1)]).is_none());
+    }
 }
@@ -1,10 +1,10 @@
 use rand::Rng;
 
-use crate::{Pcg, Sample};
+use crate::{eval::Ctx, Sample};
 
-pub fn gen_uuid(rng: &mut Pcg) -> Sample {
+pub fn gen_uuid(ctx: &mut Ctx) -> Sample {
     // https://datatracker.ietf.org/doc/html/rfc9562#section-5.4
-    let mut uuid: [u8; 16] = rng.gen();
+    let mut uuid: [u8; 16] = ctx.rng.gen();
     uuid[6] = (uuid[6] & 0x0f) | 0x40; // version (byte 6 to hex 4x -> 0x40)
     uuid[8] = (uuid[8] & 0x3f) | 0x80; // variant (byte 8 to bin 10xx_xxxx -> 0x80)
 
@@ -13,6 +13,7 @@ pub fn gen_uuid(rng: &mut Pcg) -> Sample {
         // SAFETY: only ASCII used
         std::str::from_utf8_unchecked(&bytes)
     };
+    ctx.trace(|| format!("uuid: {s}"));
     Sample::text(s.into())
 }
 
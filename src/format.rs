@@ -0,0 +1,203 @@
+//! Formatting of numeric results
+
+use std::fmt::{Display, LowerExp};
+
+/// How numeric results are displayed
+///
+/// Only the values are formatted, the expressions are always printed as
+/// written so they can be parsed again. By default, numbers are plain, like
+/// `1000.5`.
+///
+/// It may have more options in the future, so it's built from the default:
+///
+/// ```
+/// # use rng_query::NumberFormat;
+/// let fmt = NumberFormat::default()
+///     .with_grouping(Some(' '))
+///     .with_decimal(',');
+/// assert_eq!(fmt.grouping, Some(' '));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct NumberFormat {
+    /// Separates groups of 3 digits in the integer part, like `1 000`
+    pub grouping: Option<char>,
+    /// Separates the integer and fractional parts of floats, `.` by default
+    pub decimal: char,
+    /// Rounds floats to this many significant digits, like `1.235` with 4
+    ///
+    /// The trailing zeros of the fractional part are removed. This hides the
+    /// noise of the default shortest representation, like
+    /// `0.30000000000000004`, which is `0.3` with 6 digits.
+    pub significant: Option<u8>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            grouping: None,
+            decimal: '.',
+            significant: None,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Changes [`NumberFormat::grouping`]
+    pub fn with_grouping(self, grouping: Option<char>) -> Self {
+        Self { grouping, ..self }
+    }
+
+    /// Changes [`NumberFormat::decimal`]
+    pub fn with_decimal(self, decimal: char) -> Self {
+        Self { decimal, ..self }
+    }
+
+    /// Changes [`NumberFormat::significant`]
+    pub fn with_significant(self, significant: Option<u8>) -> Self {
+        Self {
+            significant,
+            ..self
+        }
+    }
+
+    pub(crate) fn int(&self, n: impl Into<i64>) -> String {
+        self.digits(&n.into().to_string())
+    }
+
+    pub(crate) fn float(&self, n: impl Display + LowerExp) -> String {
+        let s = match self.significant {
+            Some(digits) => significant(n, digits),
+            None => n.to_string(),
+        };
+        match s.split_once('.') {
+            Some((int, frac)) => format!("{}{}{frac}", self.digits(int), self.decimal),
+            None => self.digits(&s),
+        }
+    }
+
+    /// Groups an integer, with an optional sign
+    fn digits(&self, s: &str) -> String {
+        let Some(sep) = self.grouping else {
+            return s.to_string();
+        };
+        let (sign, digits) = s.split_at(s.starts_with('-') as usize);
+        // inf or NaN
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return s.to_string();
+        }
+        let mut out = String::from(sign);
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                out.push(sep);
+            }
+            out.push(c);
+        }
+        out
+    }
+}
+
+/// Writes `n` with `digits` significant digits, without exponent
+///
+/// It uses the exponent notation to round, which is always the same, and
+/// then moves the decimal point.
+fn significant(n: impl LowerExp, digits: u8) -> String {
+    let precision = digits.max(1) as usize - 1;
+    let s = format!("{n:.precision$e}");
+    // inf or NaN
+    let Some((mantissa, exp)) = s.split_once('e') else {
+        return s;
+    };
+    let exp = exp.parse::<isize>().expect("valid exponent");
+    let (sign, mantissa) = mantissa.split_at(mantissa.starts_with('-') as usize);
+    let digits = mantissa.replace('.', "");
+
+    // number of digits before the decimal point
+    let point = exp + 1;
+    let (int, frac) = if point <= 0 {
+        ("0".to_string(), "0".repeat(point.unsigned_abs()) + &digits)
+    } else if point as usize >= digits.len() {
+        (
+            digits.clone() + &"0".repeat(point as usize - digits.len()),
+            String::new(),
+        )
+    } else {
+        let (int, frac) = digits.split_at(point as usize);
+        (int.to_string(), frac.to_string())
+    };
+    let frac = frac.trim_end_matches('0');
+    if frac.is_empty() {
+        format!("{sign}{int}")
+    } else {
+        format!("{sign}{int}.{frac}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const GROUPED: NumberFormat = NumberFormat {
+        grouping: Some(' '),
+        decimal: ',',
+        significant: None,
+    };
+
+    #[test_case(0 => "0")]
+    #[test_case(999 => "999")]
+    #[test_case(1000 => "1 000")]
+    #[test_case(-1234567 => "-1 234 567")]
+    #[test_case(100000 => "100 000")]
+    fn grouped_int(n: i64) -> String {
+        GROUPED.int(n)
+    }
+
+    #[test_case(0.5 => "0,5")]
+    #[test_case(-12345.25 => "-12 345,25")]
+    #[test_case(2.0 => "2")]
+    #[test_case(f64::NEG_INFINITY => "-inf")]
+    fn comma_decimal(n: f64) -> String {
+        GROUPED.float(n)
+    }
+
+    #[test]
+    fn plain() {
+        let fmt = NumberFormat::default();
+        assert_eq!(fmt.int(-1234567), "-1234567");
+        assert_eq!(fmt.float(1234.5), "1234.5");
+    }
+
+    #[test_case(1.23456 => "1.235")]
+    #[test_case(-2.5 => "-2.5")]
+    #[test_case(0.000123456 => "0.0001235")]
+    #[test_case(98765.4 => "98770")]
+    #[test_case(1e20 => "100000000000000000000")]
+    #[test_case(0.0 => "0")]
+    #[test_case(f64::NAN => "NaN")]
+    fn significant_digits(n: f64) -> String {
+        let fmt = NumberFormat {
+            significant: Some(4),
+            ..Default::default()
+        };
+        fmt.float(n)
+    }
+
+    #[test]
+    fn significant_any_magnitude() {
+        let fmt = NumberFormat {
+            significant: Some(6),
+            ..Default::default()
+        };
+        for exp in -8..=12 {
+            let n = 1.23456789 * 10f64.powi(exp);
+            let s = fmt.float(n);
+            // the same digits, only the decimal point moves
+            let digits = s.replace('.', "");
+            let digits = digits.trim_start_matches('0').trim_end_matches('0');
+            assert_eq!(digits, "123457", "{n} is {s}");
+            // f32 and f64 of the same value are the same
+            assert_eq!(fmt.float(n as f32), s, "{n}");
+        }
+    }
+}
@@ -9,23 +9,38 @@
 //! guarantee, so you may want to pin a specific version.
 //!
 //! Run a whole input with [`run_query`] or have more control with [`State`] and
-//! its methods.
+//! its methods. To inspect a query without running it, use [`parse`].
 //!
 //! All [`Display`](std::fmt::Display) implementations of the crate *may* output ANSI color codes.
 //! Use something like [anstream](https://docs.rs/anstream/) if you dont want
 //! colors.
 
+mod ansi;
 mod ast;
+mod columns;
 mod eval;
 mod expr;
+mod format;
 mod parse;
+pub mod parsed;
+mod rng;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy;
 
-use ast::Entry;
-use eval::Eval;
+use std::{
+    collections::{BTreeMap, HashSet},
+    rc::Rc,
+};
+
+use ast::{ChooseEntry, Entry};
+pub use columns::display_columns;
 pub use eval::Sample;
+use eval::{Ctx, Eval};
+pub use format::NumberFormat;
 use parse::parse_query;
-use rand::SeedableRng;
-use rand_pcg::Pcg64 as Pcg;
+pub use parsed::ParsedQuery;
+pub use rng::Algorithm;
+use rng::Generator;
 
 macro_rules! regex {
     ($re:literal $(,)?) => {{
@@ -36,16 +51,132 @@ macro_rules! regex {
 pub(crate) use regex;
 
 /// Run a query
+///
+/// The results are a plain [`Vec`], so they can be iterated by value or by
+/// reference.
+///
+/// ```
+/// let output = rng_query::run_query("a, b, c / list")?;
+/// for sample in &output {
+///     assert!(["a", "b", "c"].contains(&sample.value().as_str()));
+/// }
+/// let values = output.into_iter().map(|s| s.value()).collect::<Vec<_>>();
+/// assert_eq!(values, ["a", "b", "c"]);
+/// # Ok::<(), rng_query::Error>(())
+/// ```
 pub fn run_query(input: &str) -> Result<Vec<Sample>, Error> {
     let mut state = State::new();
     state.run_query(input)
 }
 
+/// Parse a query without running it
+///
+/// ```
+/// use rng_query::parsed::EntryKind;
+///
+/// let query = rng_query::parse("a, 2d6 / 2")?;
+/// assert_eq!(query.entries[0].kind, EntryKind::Text("a".to_string()));
+/// assert_eq!(query.entries[1].kind, EntryKind::Expr("2d6".to_string()));
+/// # Ok::<(), rng_query::Error>(())
+/// ```
+pub fn parse(input: &str) -> Result<ParsedQuery, Error> {
+    parse::parse_query_view(input, &Separators::default())
+}
+
+/// Exact number of ways to get each result of a dice roll
+///
+/// Every combination of the faces of the dice is checked, without sampling.
+/// `None` if the roll is exploding, the result is not a whole number or there
+/// are more than 1 000 000 combinations.
+///
+/// ```
+/// let outcomes = rng_query::dice_outcomes("2d6")?.unwrap();
+/// assert_eq!(outcomes[&7], 6);
+/// assert_eq!(outcomes.values().sum::<u64>(), 36);
+/// assert_eq!(rng_query::dice_outcomes("2d6!")?, None);
+/// # Ok::<(), rng_query::Error>(())
+/// ```
+pub fn dice_outcomes(roll: &str) -> Result<Option<BTreeMap<i32, u64>>, Error> {
+    Ok(parse_roll(roll)?.enumerate_outcomes())
+}
+
+/// Like [`dice_outcomes`], with at most `limit` combinations
+pub fn dice_outcomes_with_limit(
+    roll: &str,
+    limit: u64,
+) -> Result<Option<BTreeMap<i32, u64>>, Error> {
+    Ok(parse_roll(roll)?.enumerate_outcomes_with_limit(limit))
+}
+
+fn parse_roll(roll: &str) -> Result<expr::dice::Roll, Error> {
+    roll.trim()
+        .parse::<expr::dice::Roll>()
+        .map_err(|e| Error::Expr(e.to_string()))
+}
+
 /// Query interpreter
 #[derive(Debug, Clone)]
 pub struct State {
-    rng: Pcg,
-    data: Vec<(usize, Entry)>,
+    rng: Generator,
+    data: Vec<ChooseEntry>,
+    sep: Separators,
+    fmt: NumberFormat,
+    trim_entries: bool,
+    ascii: bool,
+    truecolor: bool,
+}
+
+/// Special chars of the query structure
+///
+/// New lines always separate entries too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Separators {
+    /// Separates entries, `,` by default
+    ///
+    /// It can have more than one char, like `||`. If empty, only new lines
+    /// separate entries.
+    pub entry: String,
+    /// Starts the options, `/` by default
+    pub options: char,
+}
+
+impl Separators {
+    /// Checks that the separators can be used to parse queries
+    ///
+    /// They can't use whitespace or the chars with a meaning in a query, like
+    /// brackets or quotes, and the entry separator can't contain the options
+    /// one.
+    pub fn validate(&self) -> Result<(), Error> {
+        const RESERVED: &[char] = &['{', '}', '[', ']', '(', ')', '"', '\''];
+        let bad = |c: char| c.is_whitespace() || RESERVED.contains(&c);
+        if bad(self.options) {
+            return Err(Error::Separators(format!(
+                "invalid options separator: {:?}",
+                self.options
+            )));
+        }
+        if self.entry.contains(bad) {
+            return Err(Error::Separators(format!(
+                "invalid entry separator: {:?}",
+                self.entry
+            )));
+        }
+        if self.entry.contains(self.options) {
+            return Err(Error::Separators(
+                "the entry separator contains the options separator".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Separators {
+    fn default() -> Self {
+        Self {
+            entry: ",".to_string(),
+            options: '/',
+        }
+    }
 }
 
 impl State {
@@ -53,18 +184,202 @@ impl State {
     ///
     /// Seed is autogenerated form entropy.
     pub fn new() -> Self {
-        Self::from_rng(Pcg::from_entropy())
+        Self::with_algorithm(Algorithm::default(), None)
     }
     /// Create a new state with a seed
     pub fn with_seed(seed: u64) -> Self {
-        Self::from_rng(Pcg::seed_from_u64(seed))
+        Self::with_algorithm(Algorithm::default(), Some(seed))
+    }
+    /// Create a new state with a specific generator algorithm
+    ///
+    /// If no seed is given, it is autogenerated from entropy.
+    pub fn with_algorithm(algorithm: Algorithm, seed: Option<u64>) -> Self {
+        Self::from_rng(Generator::new(algorithm, seed))
+    }
+    /// Create a new state that takes the random values from `bytes`
+    ///
+    /// The bytes are used in order, so the same bytes always give the same
+    /// results. This allows to use an external source of randomness or to
+    /// test specific results. Running out of bytes is an [`Error::Rng`].
+    ///
+    /// Reseeding the state and the `i` flag use [`Algorithm::Pcg`].
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// // each die takes 4 bytes
+    /// let mut state = State::with_bytes([0; 8]);
+    /// assert_eq!(state.run_query("2d6")?[0].value(), "2");
+    /// assert!(state.run_query("1d6").is_err());
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn with_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Self::from_rng(Generator::from_bytes(bytes.into()))
+    }
+    /// Create a new state with custom separators
+    ///
+    /// Seed is autogenerated form entropy. Fails if the separators are not
+    /// valid, see [`Separators::validate`].
+    ///
+    /// ```
+    /// # use rng_query::{Separators, State};
+    /// let sep = Separators {
+    ///     entry: ";".to_string(),
+    ///     options: '|',
+    /// };
+    /// let mut state = State::with_separators(sep)?;
+    /// let output = state.run_query("a, b; c | list")?;
+    /// let values = output.iter().map(|s| s.value()).collect::<Vec<_>>();
+    /// assert_eq!(values, ["a, b", "c"]);
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn with_separators(sep: Separators) -> Result<Self, Error> {
+        sep.validate()?;
+        Ok(Self { sep, ..Self::new() })
+    }
+    /// Create a new state with a seed and custom separators
+    ///
+    /// Fails if the separators are not valid, see [`Separators::validate`].
+    pub fn with_seed_and_separators(seed: u64, sep: Separators) -> Result<Self, Error> {
+        sep.validate()?;
+        Ok(Self {
+            sep,
+            ..Self::with_seed(seed)
+        })
     }
-    fn from_rng(rng: Pcg) -> Self {
+    fn from_rng(rng: Generator) -> Self {
         Self {
             rng,
             data: Vec::new(),
+            sep: Separators::default(),
+            fmt: NumberFormat::default(),
+            trim_entries: true,
+            ascii: false,
+            truecolor: true,
         }
     }
+
+    /// Reseeds the generator, keeping the algorithm
+    ///
+    /// The entries added and the separators are kept, so this is the same as
+    /// [`State::with_seed`] but for an existing state.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Generator::new(self.rng.algorithm(), Some(seed));
+    }
+
+    /// Reseeds the generator from entropy, keeping the algorithm
+    pub fn reseed_from_entropy(&mut self) {
+        self.rng = Generator::new(self.rng.algorithm(), None);
+    }
+
+    /// Number of random values drawn since the generator was seeded
+    ///
+    /// Reseeding starts the count again. This is useful to check how much
+    /// randomness a query uses, as the same query with the same seed always
+    /// draws the same amount.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut state = State::with_seed(1);
+    /// assert_eq!(state.draws(), 0);
+    /// state.run_query("1d6")?;
+    /// assert!(state.draws() > 0);
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn draws(&self) -> u64 {
+        self.rng.draws()
+    }
+
+    /// Current separators
+    pub fn separators(&self) -> &Separators {
+        &self.sep
+    }
+
+    /// Change the separators used to parse the next queries
+    pub fn set_separators(&mut self, sep: Separators) {
+        self.sep = sep;
+    }
+
+    /// If the whitespace around the entries is removed
+    pub fn trim_entries(&self) -> bool {
+        self.trim_entries
+    }
+
+    /// Change if the whitespace around the entries is removed, `true` by default
+    ///
+    /// Applies to the next entries added and the next queries. The options
+    /// and expressions are always trimmed.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut state = State::new();
+    /// state.set_trim_entries(false);
+    /// state.add_data("  (o_o)  ");
+    /// let output = state.run_query("")?;
+    /// assert_eq!(output[0].value(), "  (o_o)  ");
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn set_trim_entries(&mut self, trim: bool) {
+        self.trim_entries = trim;
+    }
+
+    /// Current format of numeric results
+    pub fn number_format(&self) -> NumberFormat {
+        self.fmt
+    }
+
+    /// Change how the numeric results of the next queries are displayed
+    ///
+    /// ```
+    /// # use rng_query::{NumberFormat, State};
+    /// let mut state = State::new();
+    /// state.set_number_format(
+    ///     NumberFormat::default()
+    ///         .with_grouping(Some('.'))
+    ///         .with_decimal(','),
+    /// );
+    /// let output = state.run_query("1000d1 / all")?;
+    /// assert_eq!(output[0].value(), "1.000");
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn set_number_format(&mut self, fmt: NumberFormat) {
+        self.fmt = fmt;
+    }
+
+    /// If the results of the expressions only use ASCII
+    pub fn ascii(&self) -> bool {
+        self.ascii
+    }
+
+    /// Change if the results of the expressions only use ASCII, `false` by default
+    ///
+    /// For terminals without UTF-8. A coin is `H` or `T` and a color is only
+    /// its hex code, like `#FF8000`, without the swatch. The entries and char
+    /// classes are not changed.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut state = State::new();
+    /// state.set_ascii(true);
+    /// let output = state.run_query("coin")?;
+    /// assert!(["H", "T"].contains(&output[0].value().as_str()));
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn set_ascii(&mut self, ascii: bool) {
+        self.ascii = ascii;
+    }
+
+    /// If color swatches use 24 bit colors
+    pub fn truecolor(&self) -> bool {
+        self.truecolor
+    }
+
+    /// Change if color swatches use 24 bit colors, `true` by default
+    ///
+    /// Without them, the swatch is the nearest of the 256 color palette. The
+    /// hex code is always the exact color.
+    pub fn set_truecolor(&mut self, truecolor: bool) {
+        self.truecolor = truecolor;
+    }
 }
 
 impl Default for State {
@@ -78,47 +393,252 @@ impl State {
     ///
     /// It will consume entries from the state if any.
     pub fn run_query(&mut self, input: &str) -> Result<Vec<Sample>, Error> {
-        let mut ast = parse_query(input)?;
+        self.run(input, None)
+    }
+
+    /// Runs a query reporting every random draw
+    ///
+    /// Same as [`State::run_query`], but `trace` is called with a description
+    /// of each step that uses the RNG, like selecting entries or rolling a
+    /// die. Useful for debugging.
+    pub fn run_query_traced(
+        &mut self,
+        input: &str,
+        mut trace: impl FnMut(&str),
+    ) -> Result<Vec<Sample>, Error> {
+        self.run(input, Some(&mut trace))
+    }
+
+    /// Evaluates a single expression, like `2d6` or `coin`
+    ///
+    /// The input is not a query, so there are no entries to select or
+    /// options. The entries of the state are not used. Text that is not an
+    /// expression is an error.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut state = State::with_seed(3);
+    /// let roll = state.eval_expression("2d6")?;
+    /// assert_eq!(roll.kind(), "dice");
+    /// let total = roll.value().parse::<u32>().unwrap();
+    /// assert!((2..=12).contains(&total));
+    ///
+    /// let coin = state.eval_expression("coin")?;
+    /// assert!(["heads", "tails"].contains(&coin.value().as_str()));
+    /// assert!(state.eval_expression("a, b").is_err());
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn eval_expression(&mut self, expr: &str) -> Result<Sample, Error> {
+        let expr = expr.trim();
+        let e = expr::parse_expr(expr)?
+            .ok_or_else(|| Error::Expr(format!("not an expression: {expr}")))?;
+        let mut ctx = Ctx::new(&mut self.rng, None);
+        ctx.fmt = self.fmt;
+        ctx.ascii = self.ascii;
+        ctx.truecolor = self.truecolor;
+        let sample = match e.eval(&mut ctx) {
+            eval::EvalRes::Single(s) => s,
+            _ => unreachable!("expressions give a single sample"),
+        };
+        if let Some(err) = ctx.take_error() {
+            return Err(err);
+        }
+        self.check_rng()?;
+        Ok(sample)
+    }
+
+    /// Fails if the generator ran out of bytes, see [`State::with_bytes`]
+    fn check_rng(&self) -> Result<(), Error> {
+        if self.rng.exhausted() {
+            return Err(Error::Rng("ran out of random bytes".to_string()));
+        }
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        input: &str,
+        trace: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<Vec<Sample>, Error> {
+        let mut ast = parse_query(input, &self.sep, self.trim_entries)?;
         if !self.data.is_empty() {
             let mut entries = std::mem::take(&mut self.data);
-            let last_id = entries.last().map(|(id, _)| *id).unwrap_or(0);
+            let last_id = entries.last().map(|e| e.id).unwrap_or(0);
 
             entries.reserve(ast.root.entries.len());
-            for (id, e) in ast.root.entries {
-                entries.push((id + last_id + 1, e));
+            for mut e in ast.root.entries {
+                e.id += last_id + 1;
+                entries.push(e);
             }
-            debug_assert!(entries.windows(2).all(|w| w[0].0 + 1 == w[1].0));
+            debug_assert!(entries.windows(2).all(|w| w[0].id + 1 == w[1].id));
             ast.root.entries = entries;
         }
-        let res = ast.eval(&mut self.rng);
+        let trace = trace.map(|t| t as &mut dyn FnMut(&str));
+        let mut ctx = Ctx::new(&mut self.rng, trace);
+        ctx.fmt = self.fmt;
+        ctx.ascii = self.ascii;
+        ctx.truecolor = self.truecolor;
+        let res = ast.eval(&mut ctx);
         let v = match res {
             eval::EvalRes::Emtpy => vec![],
             eval::EvalRes::Single(s) => vec![s],
             eval::EvalRes::Many(v) => v,
         };
+        if let Some(err) = ctx.take_error() {
+            return Err(err);
+        }
+        self.check_rng()?;
         Ok(v)
     }
 
-    fn push_entry(&mut self, entry: Entry) {
+    fn push_entry(&mut self, entry: Entry, text: &str, weight: f64) {
         let id = self.data.len();
-        self.data.push((id, entry));
+        let key = ast::text_key(text);
+        self.data
+            .push(ChooseEntry::weighted(id, entry, weight, key));
+    }
+
+    /// Shuffles a slice with the generator of the state
+    ///
+    /// This allows to reuse a seeded state for other data. It advances the
+    /// generator, so the next queries will have different results.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut a = [1, 2, 3, 4, 5];
+    /// let mut b = a;
+    /// State::with_seed(42).shuffle(&mut a);
+    /// State::with_seed(42).shuffle(&mut b);
+    /// assert_eq!(a, b);
+    /// ```
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        use rand::seq::SliceRandom;
+        slice.shuffle(&mut self.rng);
     }
 
     /// Adds data entries for the next query
     pub fn add_data(&mut self, entry: &str) {
-        self.push_entry(Entry::data(entry.trim()));
+        let entry = self.trim(entry);
+        self.push_entry(Entry::data(entry), entry, ChooseEntry::DEFAULT_WEIGHT);
+    }
+
+    /// Adds a data entry with a weight for the next query
+    ///
+    /// It's the same as a weight in the query, like `a *3`. Entries with
+    /// weight 0 are never selected randomly.
+    ///
+    /// # Panics
+    ///
+    /// If the weight is negative, infinite or NaN.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut state = State::new();
+    /// state.add_weighted_data("never", 0.0);
+    /// state.add_weighted_data("always", 2.5);
+    /// assert_eq!(state.run_query("")?[0].value(), "always");
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn add_weighted_data(&mut self, entry: &str, weight: f64) {
+        assert!(
+            weight.is_finite() && weight >= 0.0,
+            "invalid weight: {weight}"
+        );
+        let entry = self.trim(entry);
+        self.push_entry(Entry::data(entry), entry, weight);
     }
 
     /// Adds a regular entry for the next query
     pub fn add_entry(&mut self, entry: &str) -> Result<(), Error> {
-        let entry = Entry::parse(entry.trim())?;
-        self.push_entry(entry);
+        let entry = self.trim(entry);
+        self.push_entry(Entry::parse(entry)?, entry, ChooseEntry::DEFAULT_WEIGHT);
         Ok(())
     }
+
+    fn trim<'a>(&self, entry: &'a str) -> &'a str {
+        if self.trim_entries {
+            entry.trim()
+        } else {
+            entry
+        }
+    }
+
+    /// Adds the entries of `other` that are not in this state yet
+    ///
+    /// Entries are compared by their text. The entries of this state go
+    /// first, followed by the new ones in the order of `other`. Duplicates
+    /// already in this state are kept, but only the first entry with the same
+    /// text is taken from `other`. Expressions from [`State::add_entry`] have
+    /// no text, so they are always added.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut deck = State::new();
+    /// deck.add_data("a");
+    /// deck.add_data("b");
+    /// let mut other = State::new();
+    /// other.add_data("b");
+    /// other.add_data("c");
+    /// deck.union_stack(&other);
+    /// let values = deck.run_query("/ list")?.iter().map(|s| s.value()).collect::<Vec<_>>();
+    /// assert_eq!(values, ["a", "b", "c"]);
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn union_stack(&mut self, other: &State) {
+        let mut seen = self
+            .data
+            .iter()
+            .filter_map(|e| e.entry.text())
+            .map(Rc::clone)
+            .collect::<HashSet<_>>();
+        for e in &other.data {
+            if e.entry.text().is_none_or(|t| seen.insert(Rc::clone(t))) {
+                self.data.push(e.clone());
+            }
+        }
+        self.renumber_data();
+    }
+
+    /// Keeps only the entries with the same text as one in `other`
+    ///
+    /// The order and duplicates of this state are kept. Expressions from
+    /// [`State::add_entry`] have no text, so they are always removed.
+    ///
+    /// ```
+    /// # use rng_query::State;
+    /// let mut deck = State::new();
+    /// deck.add_data("a");
+    /// deck.add_data("b");
+    /// deck.add_data("b");
+    /// let mut other = State::new();
+    /// other.add_data("b");
+    /// other.add_data("c");
+    /// deck.intersect_stack(&other);
+    /// let values = deck.run_query("/ list")?.iter().map(|s| s.value()).collect::<Vec<_>>();
+    /// assert_eq!(values, ["b", "b"]);
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn intersect_stack(&mut self, other: &State) {
+        let texts = other
+            .data
+            .iter()
+            .filter_map(|e| e.entry.text())
+            .collect::<HashSet<_>>();
+        self.data
+            .retain(|e| e.entry.text().is_some_and(|t| texts.contains(t)));
+        self.renumber_data();
+    }
+
+    fn renumber_data(&mut self) {
+        for (id, e) in self.data.iter_mut().enumerate() {
+            e.id = id;
+        }
+    }
 }
 
 /// Query error
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Parsing options
     Options(String),
@@ -126,6 +646,10 @@ pub enum Error {
     Expr(String),
     /// Query structure error
     ParseQuery(String),
+    /// Invalid [`Separators`]
+    Separators(String),
+    /// The generator can't give more random values
+    Rng(String),
 }
 
 impl std::fmt::Display for Error {
@@ -134,6 +658,8 @@ impl std::fmt::Display for Error {
             Error::Options(e) => write!(f, "options: {e}"),
             Error::Expr(e) => write!(f, "expresions: {e}"),
             Error::ParseQuery(e) => write!(f, "query structure: {e}"),
+            Error::Separators(e) => write!(f, "separators: {e}"),
+            Error::Rng(e) => write!(f, "generator: {e}"),
         }
     }
 }
@@ -1,10 +1,20 @@
-use std::rc::Rc;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-use crate::{ast, regex, Error};
+use crate::{
+    ast,
+    expr::{
+        binding::{Binding, Reference},
+        interval::{Interval, Num},
+    },
+    parsed, regex, Error, Separators,
+};
 
 #[derive(Debug)]
 struct Query<'a> {
-    entries: Vec<Entry<'a>>,
+    entries: Vec<(Entry<'a>, Option<f64>)>,
     options: Option<&'a str>,
 }
 
@@ -53,47 +63,83 @@ impl<'a> Cursor<'a> {
     }
 
     fn eat_until(&mut self, f: impl Fn(char) -> bool) -> bool {
-        let mut last = '\0';
+        let mut escaped = false;
         while let Some(c) = self.first() {
-            if last != '\\' && f(c) {
+            if !escaped && f(c) {
                 return true;
             }
-            last = c;
+            escaped = !escaped && c == '\\';
             self.eat();
         }
         false
     }
 }
 
-fn parse_query_rec<'a>(cursor: &mut Cursor<'a>, is_root: bool) -> Result<Query<'a>, String> {
+/// Parses a query until the end or the closing `}` if not `is_root`
+///
+/// With `trim`, the whitespace around each entry is removed.
+fn parse_query_rec<'a>(
+    cursor: &mut Cursor<'a>,
+    is_root: bool,
+    sep: &Separators,
+    trim: bool,
+) -> Result<Query<'a>, String> {
     let mut entries = Vec::new();
     let mut options = None;
+    let mut leading_options = false;
 
     cursor.set_start(); // mark start
 
-    fn take_entry<'a>(cursor: &mut Cursor<'a>, trim_last: bool) -> Entry<'a> {
+    // takes the entry until the cursor, without the last `skip` bytes
+    let take_entry = |cursor: &mut Cursor<'a>, skip: usize| {
         let mut s = cursor.take_slice();
-        if trim_last && !s.is_empty() {
-            s = &s[..s.len() - 1]; // this may be a problem with utf8 codepoints
+        s = &s[..s.len() - skip];
+        if trim || s.trim().is_empty() {
+            s = s.trim();
         }
-        s = s.trim();
-        Entry::Text(s)
+        let (s, weight) = split_weight(s)?;
+        Ok::<_, String>((Entry::Text(s), weight))
+    };
+
+    // a weight alone after a subquery belongs to it
+    fn push_entry<'a>(
+        entries: &mut Vec<(Entry<'a>, Option<f64>)>,
+        entry: (Entry<'a>, Option<f64>),
+    ) -> Result<(), String> {
+        match (entry, entries.last_mut()) {
+            ((Entry::Text(""), Some(w)), Some((Entry::Query(_), weight @ None))) => {
+                *weight = Some(w);
+            }
+            ((Entry::Text(""), Some(_)), _) => {
+                return Err("weight without an entry".to_string());
+            }
+            (entry, _) => entries.push(entry),
+        }
+        Ok(())
     }
 
     let mut end_found = false;
-    while let Some(c) = cursor.eat() {
+    loop {
+        let rest = cursor.chars.as_str();
+        let Some(c) = cursor.eat() else { break };
         match c {
             '{' => {
-                let q = parse_query_rec(cursor, false)?;
-                entries.push(Entry::Query(Box::new(q)));
+                let before = &cursor.input[cursor.slice_start..cursor.current_pos() - 1];
+                let q = parse_query_rec(cursor, false, sep, trim)?;
+                let q = if before.trim() == "either" {
+                    parse_either(cursor, q, sep, trim)?
+                } else {
+                    q
+                };
+                entries.push((Entry::Query(Box::new(q)), None));
             }
             '}' => {
                 end_found = true;
                 if is_root {
                     return Err("unexpected '}'".to_string());
                 }
-                if options.is_none() {
-                    entries.push(take_entry(cursor, true)); // push last entry
+                if options.is_none() || leading_options {
+                    push_entry(&mut entries, take_entry(cursor, 1)?)?; // push last entry
                 }
                 cursor.set_start(); // skip '}' for next slice
                 break;
@@ -112,13 +158,36 @@ fn parse_query_rec<'a>(cursor: &mut Cursor<'a>, is_root: bool) -> Result<Query<'
                 }
                 cursor.eat();
             }
-            ',' | '\n' => {
-                entries.push(take_entry(cursor, true));
+            '\n' => {
+                push_entry(&mut entries, take_entry(cursor, 1)?)?;
             }
-            '/' => {
-                entries.push(take_entry(cursor, true)); // push last entry
+            _ if !sep.entry.is_empty() && rest.starts_with(&sep.entry) => {
+                // the first char is already eaten
+                for _ in sep.entry.chars().skip(1) {
+                    cursor.eat();
+                }
+                push_entry(&mut entries, take_entry(cursor, sep.entry.len())?)?;
+            }
+            c if c == sep.options => {
+                let (last, weight) = take_entry(cursor, c.len_utf8())?;
+                let is_first = entries.is_empty() && matches!(last, Entry::Text(""));
+                push_entry(&mut entries, (last, weight))?; // push last entry
+
+                if leading_options {
+                    return Err("both leading and trailing options".to_string());
+                }
+                if is_first {
+                    if let Some(s) = take_leading_options(cursor, sep) {
+                        options = Some(s);
+                        leading_options = true;
+                        continue;
+                    }
+                }
 
-                cursor.eat_until(|c| c == '}');
+                let end = cursor.current_pos() + options_len(cursor.chars.as_str());
+                while cursor.current_pos() < end {
+                    cursor.eat();
+                }
                 let s = cursor.take_slice().trim();
                 if s.is_empty() {
                     return Err("empty options".to_string());
@@ -134,10 +203,10 @@ fn parse_query_rec<'a>(cursor: &mut Cursor<'a>, is_root: bool) -> Result<Query<'
     if !is_root && !end_found {
         return Err("missing '}'".to_string());
     }
-    if is_root && options.is_none() {
-        entries.push(take_entry(cursor, false));
+    if is_root && (options.is_none() || leading_options) {
+        push_entry(&mut entries, take_entry(cursor, 0)?)?;
     }
-    entries.retain(|e| {
+    entries.retain(|(e, _)| {
         if let Entry::Text(s) = e {
             !s.is_empty()
         } else {
@@ -148,12 +217,213 @@ fn parse_query_rec<'a>(cursor: &mut Cursor<'a>, is_root: bool) -> Result<Query<'
     Ok(Query { entries, options })
 }
 
+/// Parses the second subquery of `either {a} {b}`, after the first one
+///
+/// It's the same as `{{a}, {b}}`, a subquery that selects one of the two.
+fn parse_either<'a>(
+    cursor: &mut Cursor<'a>,
+    first: Query<'a>,
+    sep: &Separators,
+    trim: bool,
+) -> Result<Query<'a>, String> {
+    cursor.eat_until(|c| !c.is_whitespace() || c == '\n');
+    if cursor.eat() != Some('{') {
+        return Err("either needs two subqueries, like 'either {a} {b}'".to_string());
+    }
+    let second = parse_query_rec(cursor, false, sep, trim)?;
+    Ok(Query {
+        entries: vec![
+            (Entry::Query(Box::new(first)), None),
+            (Entry::Query(Box::new(second)), None),
+        ],
+        options: None,
+    })
+}
+
+/// Takes the options before the entries, like in `/3 a, b, c`
+///
+/// Only when the text after the separator are not options on their own, so
+/// `/ all o` still has trailing options with no entries. Leading options are
+/// the longest run of words of the first entry that are valid options and
+/// leave some text for the entry, so `/3 r a, b` has `3 r` and `a`. If there
+/// is none, the first word is taken to report its error.
+fn take_leading_options<'a>(cursor: &mut Cursor<'a>, sep: &Separators) -> Option<&'a str> {
+    let rest = cursor.chars.as_str();
+    let trailing = rest[..options_len(rest)].trim();
+    if trailing.is_empty() || ast_options(trailing).is_ok() {
+        return None;
+    }
+
+    let rest = rest.trim_start();
+    let mut first_end = rest.find(['\n', '{', '}']).unwrap_or(rest.len());
+    if !sep.entry.is_empty() {
+        first_end = first_end.min(rest.find(&sep.entry).unwrap_or(rest.len()));
+    }
+    let first = &rest[..first_end];
+    let before_query = rest[first_end..].starts_with('{');
+    let word_end = first.find(char::is_whitespace).unwrap_or(first.len());
+    // ends of the words, from the last one
+    let len = first
+        .char_indices()
+        .rev()
+        .filter(|&(i, c)| c.is_whitespace() && !first[..i].ends_with(char::is_whitespace))
+        .map(|(i, _)| i)
+        .find(|&i| {
+            (before_query || !first[i..].trim().is_empty()) && ast_options(&first[..i]).is_ok()
+        })
+        .unwrap_or(word_end);
+    if len == 0 {
+        return None;
+    }
+
+    cursor.eat_until(|c| !c.is_whitespace());
+    cursor.set_start();
+    let end = cursor.current_pos() + len;
+    while cursor.current_pos() < end {
+        cursor.eat();
+    }
+    Some(cursor.take_slice())
+}
+
+/// Length of the options at the start of `s`, until the `}` that closes the
+/// subquery or the end
+///
+/// A `}` inside the quotes of a `fmt "..."` template doesn't end them.
+fn options_len(s: &str) -> usize {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '}' if !quoted => return i,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+/// Splits the trailing `*N` weight of an entry, if any
+///
+/// Fails if the weight is too big to be a finite number.
+fn split_weight(s: &str) -> Result<(&str, Option<f64>), String> {
+    let re = regex!(r"\A(?s:(.*?))\s*\*\s*(\d+(?:\.\d+)?)\z");
+    match re.captures(s) {
+        Some(caps) => {
+            let entry = caps.get(1).unwrap().as_str();
+            let weight = caps[2]
+                .parse::<f64>()
+                .ok()
+                .filter(|w| w.is_finite())
+                .ok_or_else(|| "weight too big".to_string())?;
+            Ok((entry, Some(weight)))
+        }
+        None => Ok((s, None)),
+    }
+}
+
+/// Parsed expressions by their text, so the same entry is only parsed once
+///
+/// Parsed entries don't change when evaluated, so they can be shared.
+///
+/// Named expressions are shared too, their result is stored in the
+/// [`Ctx`](crate::eval::Ctx) when evaluated.
+#[derive(Default)]
+struct EntryCache<'a> {
+    exprs: HashMap<&'a str, ast::Entry>,
+    bindings: HashMap<&'a str, Rc<Binding>>,
+    /// Entries that name an expression, others like `go as planned` are text
+    named: HashSet<&'a str>,
+}
+
+impl EntryCache<'_> {
+    /// A named expression, like `1d20 as atk`, or a reference to one, like `atk+5`
+    fn binding_entry(&self, s: &str) -> Option<ast::Entry> {
+        if self.bindings.is_empty() {
+            return None;
+        }
+        if let Some((_, name)) = split_binding(s).filter(|_| self.named.contains(s.trim())) {
+            let binding = self.bindings.get(name)?;
+            return Some(ast::Entry::Expr(Rc::clone(binding) as _));
+        }
+        let re = regex!(r"\A([a-zA-Z_]\w*)\s*(?:([+-])\s*(\d+))?\z");
+        let caps = re.captures(s.trim())?;
+        let binding = self.bindings.get(&caps[1])?;
+        let modifier = match caps.get(3) {
+            Some(n) => {
+                let n = n.as_str().parse::<i64>().ok()?;
+                if &caps[2] == "-" {
+                    -n
+                } else {
+                    n
+                }
+            }
+            None => 0,
+        };
+        let reference = Reference::new(Rc::clone(binding), modifier);
+        Some(ast::Entry::Expr(Rc::new(reference)))
+    }
+}
+
+/// Splits a named expression, like `1d20 as atk`, in the expression and the name
+fn split_binding(s: &str) -> Option<(&str, &str)> {
+    let re = regex!(r"\A(?s:(.+?))\s+as\s+([a-zA-Z_]\w*)\z");
+    let caps = re.captures(s.trim())?;
+    Some((caps.get(1).unwrap().as_str(), caps.get(2).unwrap().as_str()))
+}
+
+/// Finds the named expressions of the query before building it, so they can
+/// be referenced from any entry, even the ones before
+///
+/// Entries like `<text> as <name>` are only named expressions if `<text>` is
+/// an expression, otherwise they are text.
+fn collect_bindings<'a>(q: &Query<'a>, cache: &mut EntryCache<'a>) -> Result<(), Error> {
+    let text = q
+        .options
+        .and_then(|o| ast_options(o).ok())
+        .is_some_and(|o| o.text);
+    if text {
+        return Ok(());
+    }
+    for (entry, _) in &q.entries {
+        let s = match entry {
+            Entry::Query(q) => {
+                collect_bindings(q, cache)?;
+                continue;
+            }
+            Entry::Text(s) => s,
+        };
+        let Some((expr, name)) = split_binding(s) else {
+            continue;
+        };
+        let Some(expr) = crate::expr::parse_expr(expr)? else {
+            continue;
+        };
+        if cache.bindings.contains_key(name) {
+            return Err(Error::ParseQuery(format!("duplicate name: {name}")));
+        }
+        if crate::expr::parse_expr(name)?.is_some() {
+            return Err(Error::ParseQuery(format!(
+                "bad name, it is an expression: {name}"
+            )));
+        }
+        cache.named.insert(s.trim());
+        cache
+            .bindings
+            .insert(name, Rc::new(Binding::new(name, expr)));
+    }
+    Ok(())
+}
+
 fn build_ast(q: &Query) -> Result<ast::Query, Error> {
-    let root = ast_choose(q)?;
+    let mut cache = EntryCache::default();
+    collect_bindings(q, &mut cache)?;
+    let root = ast_choose(q, &mut cache)?;
     Ok(ast::Query { root })
 }
 
-fn ast_choose(q: &Query) -> Result<ast::Choose, Error> {
+fn ast_choose<'a>(q: &Query<'a>, cache: &mut EntryCache<'a>) -> Result<ast::Choose, Error> {
     let options = if let Some(options) = q.options {
         ast_options(options)?
     } else {
@@ -161,45 +431,234 @@ fn ast_choose(q: &Query) -> Result<ast::Choose, Error> {
     };
 
     let mut entries = Vec::with_capacity(q.entries.len());
-    for (id, entry) in q.entries.iter().enumerate() {
-        let e = ast_entry(entry, options.text)?;
-        entries.push((id, e));
+    for (entry, weight) in &q.entries {
+        let weight = weight.unwrap_or(ast::ChooseEntry::DEFAULT_WEIGHT);
+        if let Some(range) = expand_range(entry)? {
+            for n in range {
+                let n = n.to_string();
+                let key = ast::text_key(&n);
+                let e = ast::Entry::data(&n);
+                entries.push(ast::ChooseEntry::weighted(entries.len(), e, weight, key));
+            }
+            continue;
+        }
+        let e = ast_entry(entry, options.text, cache)?;
+        let key = entry_key(entry);
+        entries.push(ast::ChooseEntry::weighted(entries.len(), e, weight, key));
     }
 
     Ok(ast::Choose { entries, options })
 }
 
-fn ast_entry(entry: &Entry, always_text: bool) -> Result<ast::Entry, Error> {
+fn parsed_choose<'a>(
+    q: &Query<'a>,
+    cache: &mut EntryCache<'a>,
+) -> Result<parsed::ParsedQuery, Error> {
+    let options = if let Some(options) = q.options {
+        ast_options(options)?
+    } else {
+        ast::ChooseOptions::default()
+    };
+
+    let mut entries = Vec::with_capacity(q.entries.len());
+    for (entry, weight) in &q.entries {
+        let weight = weight.unwrap_or(ast::ChooseEntry::DEFAULT_WEIGHT);
+        if let Some(range) = expand_range(entry)? {
+            entries.extend(range.map(|n| parsed::Entry {
+                weight,
+                kind: parsed::EntryKind::Text(n.to_string()),
+            }));
+            continue;
+        }
+        let kind = match entry {
+            Entry::Query(q) => parsed::EntryKind::Query(parsed_choose(q, cache)?),
+            Entry::Text(s) => match ast_entry(entry, options.text, cache)? {
+                ast::Entry::Text(t) => parsed::EntryKind::Text(t.to_string()),
+                ast::Entry::Expr(_) => parsed::EntryKind::Expr(s.to_string()),
+            },
+        };
+        entries.push(parsed::Entry { weight, kind });
+    }
+
+    Ok(parsed::ParsedQuery {
+        entries,
+        options: (&options).into(),
+    })
+}
+
+/// Key of an entry as written, see [`ast::ChooseEntry::key`]
+fn entry_key(entry: &Entry) -> u64 {
+    match entry {
+        Entry::Text(s) => ast::text_key(s),
+        Entry::Query(q) => {
+            let mut key = ast::text_key("{");
+            for (e, weight) in &q.entries {
+                key = ast::mix_key(key, &entry_key(e).to_le_bytes());
+                if let Some(w) = weight {
+                    key = ast::mix_key(key, &w.to_le_bytes());
+                }
+            }
+            ast::mix_key(key, q.options.unwrap_or_default().as_bytes())
+        }
+    }
+}
+
+/// Max number of entries from a single `expand`
+const MAX_EXPAND: i64 = 100_000;
+
+/// Range of an `expand a..b` entry, each number is a text entry
+///
+/// This is part of the query structure, so it is expanded even with the `t`
+/// flag.
+fn expand_range(entry: &Entry) -> Result<Option<std::ops::RangeInclusive<i64>>, Error> {
+    let Entry::Text(s) = entry else {
+        return Ok(None);
+    };
+    let re = regex!(r"\Aexpand\s+([+-]?\d+)\.\.(=)?([+-]?\d+)\z");
+    let Some(caps) = re.captures(s.trim()) else {
+        return Ok(None);
+    };
+    let parse = |n: &str| {
+        n.parse::<i64>()
+            .map_err(|e| Error::ParseQuery(format!("bad expand bound: {e}")))
+    };
+    let start = parse(&caps[1])?;
+    let mut end = parse(&caps[3])?;
+    if caps.get(2).is_none() {
+        end = end.saturating_sub(1);
+    }
+    if start > end {
+        return Err(Error::ParseQuery(format!("empty expand range: {s}")));
+    }
+    if (end as i128 - start as i128) >= MAX_EXPAND as i128 {
+        return Err(Error::ParseQuery(format!(
+            "expand range too big, the max is {MAX_EXPAND} entries"
+        )));
+    }
+    Ok(Some(start..=end))
+}
+
+fn ast_entry<'a>(
+    entry: &Entry<'a>,
+    always_text: bool,
+    cache: &mut EntryCache<'a>,
+) -> Result<ast::Entry, Error> {
     let e = match entry {
-        Entry::Query(q) => ast::Entry::Expr(Rc::new(ast_choose(q)?)),
+        Entry::Query(q) => match num_set(q) {
+            Some(set) if !always_text => ast::Entry::Expr(Rc::new(set)),
+            _ => ast::Entry::Expr(Rc::new(ast_choose(q, cache)?)),
+        },
+        Entry::Text(e) if always_text => ast::Entry::data(e),
         Entry::Text(e) => {
-            if always_text {
-                ast::Entry::data(e)
-            } else {
-                ast::Entry::parse(e)?
+            if let Some(binding) = cache.binding_entry(e) {
+                return Ok(binding);
+            }
+            if let Some(cached) = cache.exprs.get(e) {
+                return Ok(cached.clone());
             }
+            let parsed = ast::Entry::parse(e)?;
+            cache.exprs.insert(e, parsed.clone());
+            parsed
         }
     };
     Ok(e)
 }
 
+/// A subquery of only numbers, like `{1, 3, 5}`, is an interval of them
+///
+/// The result is a number, like the other intervals, instead of a text entry.
+/// Only if every number is written as it would be printed, so `{007, +3}`
+/// keeps the text.
+fn num_set(q: &Query) -> Option<Interval> {
+    if q.options.is_some() {
+        return None;
+    }
+    let values = q
+        .entries
+        .iter()
+        .map(|(entry, weight)| match (entry, weight) {
+            (Entry::Text(s), None) => {
+                let s = s.trim();
+                s.parse::<Num>().ok().filter(|n| n.to_string() == s)
+            }
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Interval::set(values)
+}
+
 fn ast_options(s: &str) -> Result<ast::ChooseOptions, Error> {
-    match s {
-        "shuffle" => return Ok(ast::ChooseOptions::shuffle()),
-        "list" => return Ok(ast::ChooseOptions::list()),
-        _ => {}
+    let (s, template) = split_template(s);
+    let (s, words) = split_words(s)?;
+    let mut options = match s {
+        "shuffle" => ast::ChooseOptions::shuffle(),
+        "list" => ast::ChooseOptions::list(),
+        _ => ast_flags(s)?,
     };
+    options.template = template.map(Rc::from);
+    options.debug = words.contains(&"debug");
+    options.tally = words.contains(&"tally");
+    options.split = words.contains(&"split");
+    Ok(options)
+}
 
-    let re = regex!(r"\A(all\b|(?:[0-9]+))?([ rot]*)\z");
+/// Splits the trailing words of the options, like `debug` or `tally`
+///
+/// They can be in any order, but not repeated.
+fn split_words(mut s: &str) -> Result<(&str, Vec<&str>), Error> {
+    let re = regex!(r"\A(?s:(.*?))\s*\b(debug|tally|split)\z");
+    let mut words = Vec::new();
+    while let Some(caps) = re.captures(s) {
+        let word = caps.get(2).unwrap().as_str();
+        if words.contains(&word) {
+            return Err(Error::Options(format!("Duplicate option: {word}")));
+        }
+        words.push(word);
+        s = caps.get(1).unwrap().as_str();
+    }
+    Ok((s, words))
+}
+
+/// Splits the trailing `fmt "..."` template of the options, if any
+fn split_template(s: &str) -> (&str, Option<String>) {
+    let re = regex!(r#"\A(?s:(.*?))\s*fmt\s*"((?:[^"\\]|\\.)*)"\z"#);
+    let Some(caps) = re.captures(s) else {
+        return (s, None);
+    };
+    let mut template = String::with_capacity(caps[2].len());
+    let mut chars = caps[2].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => template.extend(chars.next()),
+            c => template.push(c),
+        }
+    }
+    (caps.get(1).unwrap().as_str(), Some(template))
+}
+
+fn ast_flags(s: &str) -> Result<ast::ChooseOptions, Error> {
+    let re = regex!(r"\A(all\b|(?:[0-9]+(?:\s*-\s*[0-9]+)?))?([ rotui]*)\z");
     let cap = re
         .captures(s)
         .ok_or_else(|| Error::Options(format!("Bad options: {s:?}")))?;
+    let parse_amount = |n: &str| {
+        n.trim()
+            .parse::<u32>()
+            .map_err(|e| Error::Options(format!("Bad amount: {e}")))
+    };
     let amount = match cap.get(1).map(|m| m.as_str().trim_end()) {
         Some("all") => ast::Amount::All,
-        Some(n) => n
-            .parse::<u32>()
-            .map(ast::Amount::N)
-            .map_err(|e| Error::Options(format!("Bad amount: {e}")))?,
+        Some(n) => match n.split_once('-') {
+            Some((low, high)) => {
+                let low = parse_amount(low)?;
+                let high = parse_amount(high)?;
+                if low > high {
+                    return Err(Error::Options(format!("Bad amount range: {low} > {high}")));
+                }
+                ast::Amount::Range(low, high)
+            }
+            None => parse_amount(n).map(ast::Amount::N)?,
+        },
         None => ast::Amount::N(1),
     };
 
@@ -220,17 +679,269 @@ fn ast_options(s: &str) -> Result<ast::ChooseOptions, Error> {
     let repeating = flags.contains(&'r');
     let keep_order = flags.contains(&'o');
     let text = flags.contains(&'t');
+    let unique = flags.contains(&'u');
+    let independent = flags.contains(&'i');
 
     Ok(ast::ChooseOptions {
         amount,
         repeating,
         keep_order,
         text,
+        unique,
+        independent,
+        ..Default::default()
     })
 }
 
-pub fn parse_query(input: &str) -> Result<ast::Query, Error> {
+pub fn parse_query(input: &str, sep: &Separators, trim: bool) -> Result<ast::Query, Error> {
     let mut cursor = Cursor::new(input);
-    let q = parse_query_rec(&mut cursor, true).map_err(Error::ParseQuery)?;
+    let q = parse_query_rec(&mut cursor, true, sep, trim).map_err(Error::ParseQuery)?;
     build_ast(&q)
 }
+
+pub fn parse_query_view(input: &str, sep: &Separators) -> Result<parsed::ParsedQuery, Error> {
+    let mut cursor = Cursor::new(input);
+    let q = parse_query_rec(&mut cursor, true, sep, true).map_err(Error::ParseQuery)?;
+    let mut cache = EntryCache::default();
+    collect_bindings(&q, &mut cache)?;
+    parsed_choose(&q, &mut cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry_texts(input: &str, sep: &Separators) -> Vec<String> {
+        let mut cursor = Cursor::new(input);
+        let q = parse_query_rec(&mut cursor, true, sep, true).expect("failed to parse");
+        q.entries
+            .iter()
+            .map(|(e, _)| match e {
+                Entry::Text(t) => t.to_string(),
+                Entry::Query(q) => format!("{{{}}}", q.options.unwrap_or_default()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn custom_options_separator() {
+        let sep = Separators {
+            options: '|',
+            ..Default::default()
+        };
+        assert_eq!(entry_texts("{a,b|2}", &sep), ["{2}"]);
+        assert_eq!(entry_texts("a/b, c | 2", &sep), ["a/b", "c"]);
+
+        let default = Separators::default();
+        assert_eq!(entry_texts("a|b, c / 2", &default), ["a|b", "c"]);
+    }
+
+    #[test]
+    fn leading_options() {
+        let options = |input: &str| {
+            let mut cursor = Cursor::new(input);
+            let q = parse_query_rec(&mut cursor, true, &Separators::default(), true).unwrap();
+            q.options.map(str::to_string)
+        };
+        let default = Separators::default();
+        assert_eq!(entry_texts("/3 a, b, c", &default), ["a", "b", "c"]);
+        assert_eq!(options("/3 a, b, c").as_deref(), Some("3"));
+        assert_eq!(entry_texts("  / all\na\nb", &default), ["a", "b"]);
+        assert_eq!(options("/ 2o a, b").as_deref(), Some("2o"));
+        assert_eq!(entry_texts("{/2 a, b}, c", &default), ["{2}", "c"]);
+        assert_eq!(entry_texts("a, {/2 {b, c / 1}, d}", &default), ["a", "{2}"]);
+        assert_eq!(entry_texts("/3 r a, b", &default), ["a", "b"]);
+        assert_eq!(options("/3 r a, b").as_deref(), Some("3 r"));
+        assert_eq!(entry_texts("/2 i u, v", &default), ["u", "v"]);
+        assert_eq!(options("/2 i u, v").as_deref(), Some("2 i"));
+        assert_eq!(
+            options("/ all o tally x\ny").as_deref(),
+            Some("all o tally")
+        );
+        assert_eq!(options("/2 r {a, b}, c").as_deref(), Some("2 r"));
+
+        // the whole text is options, without entries
+        assert_eq!(options("/ all o").as_deref(), Some("all o"));
+        assert_eq!(options("/ 2").as_deref(), Some("2"));
+        assert!(entry_texts("/ all o", &default).is_empty());
+    }
+
+    #[test]
+    fn either() {
+        let default = Separators::default();
+        assert_eq!(entry_texts("either {a / 2} {b}, c", &default), ["{}", "c"]);
+        let mut cursor = Cursor::new("either {a} {b}");
+        let q = parse_query_rec(&mut cursor, true, &default, true).unwrap();
+        let Entry::Query(either) = &q.entries[0].0 else {
+            panic!("not a subquery: {q:?}");
+        };
+        assert_eq!(either.entries.len(), 2);
+
+        for input in [
+            "either {a}",
+            "either {a}, {b}",
+            "either {a} b",
+            "either {a}\n{b}",
+        ] {
+            let mut cursor = Cursor::new(input);
+            let res = parse_query_rec(&mut cursor, true, &default, true);
+            assert!(res.is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn shared_entries() {
+        let sep = Separators::default();
+        let q = parse_query("1d6, 1d6, 2d6, {1d6} / all o", &sep, true).unwrap();
+        let exprs = q
+            .root
+            .entries
+            .iter()
+            .map(|e| match &e.entry {
+                ast::Entry::Expr(expr) => expr.clone(),
+                ast::Entry::Text(t) => panic!("not an expression: {t}"),
+            })
+            .collect::<Vec<_>>();
+        assert!(Rc::ptr_eq(&exprs[0], &exprs[1]));
+        assert!(!Rc::ptr_eq(&exprs[0], &exprs[2]));
+
+        // text entries are not parsed
+        let q = parse_query("1d6, 1d6 / list", &sep, true).unwrap();
+        assert!(q.root.entries.iter().all(|e| e.entry.text().is_some()));
+    }
+
+    #[test]
+    fn bindings() {
+        let sep = Separators::default();
+        assert!(parse_query("1d20 as atk, atk+5, atk - 1, atk", &sep, true).is_ok());
+        assert!(parse_query("atk+5, {1d20 as atk}", &sep, true).is_ok());
+
+        for input in [
+            "1d20 as atk, 1d6 as atk",
+            "1d20 as atk, {1d6 as atk}",
+            "1d20 as coin",
+        ] {
+            assert!(parse_query(input, &sep, true).is_err(), "{input}");
+        }
+
+        // only expressions are named, the rest is text
+        for input in [
+            "go as planned, stay home",
+            "Sword as weapon, Shield",
+            "Sword as atk, 1d20 as atk, atk+1",
+        ] {
+            let q = parse_query(input, &sep, true).unwrap();
+            let text = q.root.entries[0].entry.text().map(|t| t.to_string());
+            assert_eq!(text.as_deref(), input.split(", ").next(), "{input}");
+        }
+
+        // text entries are not named
+        let q = parse_query("1d20 as atk, atk / list", &sep, true).unwrap();
+        assert!(q.root.entries.iter().all(|e| e.entry.text().is_some()));
+        // without the name it's just text
+        let q = parse_query("atk+5", &sep, true).unwrap();
+        assert!(q.root.entries[0].entry.text().is_some());
+    }
+
+    #[test]
+    fn leading_and_trailing_options() {
+        let mut cursor = Cursor::new("/2 a, b / 1");
+        let res = parse_query_rec(&mut cursor, true, &Separators::default(), true);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn parsed_view() {
+        use parsed::{Amount, EntryKind};
+
+        let sep = Separators::default();
+        let q = parse_query_view("a, 'b' * 2, {1d6, [1..3] / all o}, coin / 2 u", &sep).unwrap();
+        assert_eq!(q.options.amount, Amount::N(2));
+        assert!(q.options.unique && !q.options.repeating);
+        assert_eq!(q.entries.len(), 4);
+        assert_eq!(q.entries[0].kind, EntryKind::Text("a".to_string()));
+        assert_eq!(q.entries[1].kind, EntryKind::Text("b".to_string()));
+        assert_eq!(q.entries[1].weight, 2.0);
+        assert_eq!(q.entries[3].kind, EntryKind::Expr("coin".to_string()));
+
+        let EntryKind::Query(sub) = &q.entries[2].kind else {
+            panic!("not a subquery")
+        };
+        assert_eq!(sub.options.amount, Amount::All);
+        assert!(sub.options.keep_order);
+        let kinds = sub.entries.iter().map(|e| &e.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                &EntryKind::Expr("1d6".to_string()),
+                &EntryKind::Expr("[1..3]".to_string())
+            ]
+        );
+
+        let text = parse_query_view("1d6, 2d6 / list", &sep).unwrap();
+        assert!(matches!(text.entries[0].kind, EntryKind::Text(_)));
+        assert!(parse_query_view("a, b / nope", &sep).is_err());
+
+        let debug = parse_query_view("a, b / list debug", &sep).unwrap();
+        assert!(debug.options.debug && debug.options.keep_order);
+        assert!(!q.options.debug);
+
+        let words = parse_query_view("a, b / 10 r tally debug", &sep).unwrap();
+        assert!(words.options.debug && words.options.tally);
+        let split = parse_query_view("4d6 / split", &sep).unwrap();
+        assert!(split.options.split && !split.options.tally);
+        assert_eq!(words.options.amount, Amount::N(10));
+        assert!(parse_query_view("a, b / tally tally", &sep).is_err());
+    }
+
+    #[test]
+    fn expand() {
+        use parsed::EntryKind;
+
+        let sep = Separators::default();
+        let q = parse_query_view("expand 1..=3 * 2, x", &sep).unwrap();
+        assert_eq!(q.entries.len(), 4);
+        assert_eq!(q.entries[2].kind, EntryKind::Text("3".to_string()));
+        assert_eq!(q.entries[2].weight, 2.0);
+        assert!(parse_query("expand 1..1", &sep, true).is_err());
+        assert!(parse_query("expand 0..=100000", &sep, true).is_err());
+        assert!(parse_query("expand 1..100000", &sep, true).is_ok());
+    }
+
+    #[test]
+    fn custom_entry_separator() {
+        let sep = Separators {
+            entry: ";".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(entry_texts("a, b; c\nd", &sep), ["a, b", "c", "d"]);
+    }
+
+    #[test]
+    fn multi_char_entry_separator() {
+        let sep = Separators {
+            entry: "||".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(entry_texts("a, b || c|d||e", &sep), ["a, b", "c|d", "e"]);
+        assert_eq!(entry_texts("a || {b || c / 1} / 2", &sep), ["a", "{1}"]);
+
+        let sep = Separators {
+            entry: "; ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(entry_texts("a;b; c", &sep), ["a;b", "c"]);
+
+        let sep = Separators {
+            entry: "→".to_string(),
+            options: '¦',
+        };
+        assert_eq!(entry_texts("á → é ¦ 1", &sep), ["á", "é"]);
+
+        let sep = Separators {
+            entry: String::new(),
+            ..Default::default()
+        };
+        assert_eq!(entry_texts("a, b\nc", &sep), ["a, b", "c"]);
+    }
+}
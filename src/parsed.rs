@@ -0,0 +1,130 @@
+//! Read-only view of a parsed query
+//!
+//! Useful for tools that need the structure of a query without running it.
+//! Get it with [`parse`](crate::parse).
+
+use crate::{ast, expr::interval::Interval};
+
+pub use crate::expr::interval::Num;
+
+/// A parsed query or subquery
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct ParsedQuery {
+    /// Entries in the order they are written
+    pub entries: Vec<Entry>,
+    /// Options of the query, the default ones if not written
+    pub options: Options,
+}
+
+/// An entry of a query
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Entry {
+    /// Weight for random draws, `1.0` if not written
+    pub weight: f64,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum EntryKind {
+    /// Text, without the surrounding quotes
+    Text(String),
+    /// An expression like a dice roll, as written
+    Expr(String),
+    /// A subquery
+    Query(ParsedQuery),
+}
+
+impl EntryKind {
+    /// Numeric span between the bounds of an interval, like `9` for `[1..10]`
+    ///
+    /// The bounds are taken as written, even if they are not included. `None`
+    /// if the entry is not an interval with number bounds.
+    ///
+    /// ```
+    /// use rng_query::parsed::Num;
+    ///
+    /// let query = rng_query::parse("[1..10], (0.5, 1), 2d6 / 1")?;
+    /// assert_eq!(query.entries[0].kind.interval_width(), Some(Num::Int(9)));
+    /// assert_eq!(query.entries[1].kind.interval_width(), Some(Num::Float(0.5)));
+    /// assert_eq!(query.entries[2].kind.interval_width(), None);
+    /// # Ok::<(), rng_query::Error>(())
+    /// ```
+    pub fn interval_width(&self) -> Option<Num> {
+        self.interval().map(|i| i.width())
+    }
+
+    /// Middle point between the bounds of an interval, like `5.5` for `[1..10]`
+    ///
+    /// Like [`EntryKind::interval_width`], it uses the bounds as written. For
+    /// integer intervals it's only a [`Num::Float`] if it is not a whole
+    /// number.
+    pub fn interval_midpoint(&self) -> Option<Num> {
+        self.interval().map(|i| i.midpoint())
+    }
+
+    fn interval(&self) -> Option<Interval> {
+        match self {
+            EntryKind::Expr(e) => e.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Options of a query
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Options {
+    pub amount: Amount,
+    /// `r` flag
+    pub repeating: bool,
+    /// `o` flag
+    pub keep_order: bool,
+    /// `t` flag
+    pub text: bool,
+    /// `u` flag
+    pub unique: bool,
+    /// `i` flag
+    pub independent: bool,
+    /// `fmt "..."` template
+    pub template: Option<String>,
+    /// `debug` option
+    pub debug: bool,
+    /// `tally` option
+    pub tally: bool,
+    /// `split` option
+    pub split: bool,
+}
+
+/// Amount of entries to select
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Amount {
+    All,
+    N(u32),
+    /// Random amount, both included
+    Range(u32, u32),
+}
+
+impl From<&ast::ChooseOptions> for Options {
+    fn from(options: &ast::ChooseOptions) -> Self {
+        Self {
+            amount: match options.amount {
+                ast::Amount::All => Amount::All,
+                ast::Amount::N(n) => Amount::N(n),
+                ast::Amount::Range(low, high) => Amount::Range(low, high),
+            },
+            repeating: options.repeating,
+            keep_order: options.keep_order,
+            text: options.text,
+            unique: options.unique,
+            independent: options.independent,
+            template: options.template.as_deref().map(String::from),
+            debug: options.debug,
+            tally: options.tally,
+            split: options.split,
+        }
+    }
+}
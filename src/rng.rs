@@ -0,0 +1,269 @@
+//! Pseudorandom generators
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rand_pcg::Pcg64;
+
+/// Pseudorandom generator algorithm
+///
+/// Both are deterministic, the same seed always gives the same results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// PCG64, fast with good statistical quality
+    #[default]
+    Pcg,
+    /// ChaCha with 12 rounds, better statistical quality but slower
+    ChaCha,
+}
+
+/// Generator used in the evaluation
+#[derive(Debug, Clone)]
+pub(crate) struct Generator {
+    inner: Inner,
+    /// See [`Generator::draws`]
+    draws: u64,
+}
+
+#[derive(Debug, Clone)]
+enum Inner {
+    Pcg(Pcg64),
+    ChaCha(Box<ChaCha12Rng>),
+    Bytes(ByteStream),
+}
+
+/// Generator that gives the bytes it was created with, in order
+///
+/// Numbers are read in little endian. When the bytes run out, it is marked as
+/// exhausted and continues with a [`Pcg64`] seeded from the bytes, so
+/// sampling that needs more values still ends and is still deterministic.
+#[derive(Debug, Clone)]
+struct ByteStream {
+    bytes: Vec<u8>,
+    pos: usize,
+    exhausted: bool,
+    /// Generator after running out of bytes
+    rest: Option<Pcg64>,
+}
+
+impl ByteStream {
+    fn fill(&mut self, dest: &mut [u8]) {
+        let n = dest.len().min(self.bytes.len() - self.pos);
+        dest[..n].copy_from_slice(&self.bytes[self.pos..self.pos + n]);
+        self.pos += n;
+        if n < dest.len() {
+            self.exhausted = true;
+            let bytes = &self.bytes;
+            self.rest
+                .get_or_insert_with(|| Pcg64::seed_from_u64(fnv1a(bytes)))
+                .fill_bytes(&mut dest[n..]);
+        }
+    }
+
+    fn next<const N: usize>(&mut self) -> [u8; N] {
+        let mut buf = [0; N];
+        self.fill(&mut buf);
+        buf
+    }
+}
+
+/// 64 bit FNV-1a hash, to seed the generator of an exhausted [`ByteStream`]
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Generator {
+    /// Create a generator, seeded from entropy if no seed is given
+    pub fn new(algorithm: Algorithm, seed: Option<u64>) -> Self {
+        let inner = match (algorithm, seed) {
+            (Algorithm::Pcg, Some(seed)) => Inner::Pcg(Pcg64::seed_from_u64(seed)),
+            (Algorithm::Pcg, None) => Inner::Pcg(Pcg64::from_entropy()),
+            (Algorithm::ChaCha, Some(seed)) => {
+                Inner::ChaCha(Box::new(ChaCha12Rng::seed_from_u64(seed)))
+            }
+            (Algorithm::ChaCha, None) => Inner::ChaCha(Box::new(ChaCha12Rng::from_entropy())),
+        };
+        Self { inner, draws: 0 }
+    }
+
+    /// Create a generator that gives `bytes`, see [`ByteStream`]
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let stream = ByteStream {
+            bytes,
+            pos: 0,
+            exhausted: false,
+            rest: None,
+        };
+        Self {
+            inner: Inner::Bytes(stream),
+            draws: 0,
+        }
+    }
+
+    /// Algorithm of the generator, or to create new ones from it
+    ///
+    /// A byte stream can't be created from a seed, so it's [`Algorithm::Pcg`].
+    pub fn algorithm(&self) -> Algorithm {
+        match self.inner {
+            Inner::Pcg(_) | Inner::Bytes(_) => Algorithm::Pcg,
+            Inner::ChaCha(_) => Algorithm::ChaCha,
+        }
+    }
+
+    /// If the generator ran out of bytes, only for byte streams
+    pub fn exhausted(&self) -> bool {
+        matches!(&self.inner, Inner::Bytes(s) if s.exhausted)
+    }
+
+    /// Number of values drawn since the generator was created
+    ///
+    /// Each `u32` or `u64` is one draw, and filling bytes is one draw for
+    /// every 8 bytes or less. It doesn't depend on the algorithm.
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+
+impl RngCore for Generator {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        match &mut self.inner {
+            Inner::Pcg(r) => r.next_u32(),
+            Inner::ChaCha(r) => r.next_u32(),
+            Inner::Bytes(s) => u32::from_le_bytes(s.next()),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        match &mut self.inner {
+            Inner::Pcg(r) => r.next_u64(),
+            Inner::ChaCha(r) => r.next_u64(),
+            Inner::Bytes(s) => u64::from_le_bytes(s.next()),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.draws += dest.len().div_ceil(8) as u64;
+        match &mut self.inner {
+            Inner::Pcg(r) => r.fill_bytes(dest),
+            Inner::ChaCha(r) => r.fill_bytes(dest),
+            Inner::Bytes(s) => s.fill(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.draws += dest.len().div_ceil(8) as u64;
+        match &mut self.inner {
+            Inner::Pcg(r) => r.try_fill_bytes(dest),
+            Inner::ChaCha(r) => r.try_fill_bytes(dest),
+            Inner::Bytes(s) => {
+                s.fill(dest);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    fn run(algorithm: Algorithm, seed: u64) -> Vec<String> {
+        let mut state = State::with_algorithm(algorithm, Some(seed));
+        let res = state
+            .run_query("a, b, c, 3d20, [0, 1), uuid / all")
+            .unwrap();
+        res.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn chacha_reproducible() {
+        assert_eq!(run(Algorithm::ChaCha, 9), run(Algorithm::ChaCha, 9));
+        assert_ne!(run(Algorithm::ChaCha, 9), run(Algorithm::Pcg, 9));
+    }
+
+    #[test]
+    fn set_seed() {
+        for algorithm in [Algorithm::Pcg, Algorithm::ChaCha] {
+            let entries = ["a", "b", "c", "3d20", "[0, 1)", "uuid"];
+            let mut state = State::with_algorithm(algorithm, None);
+            state.set_separators(crate::Separators {
+                entry: ";".to_string(),
+                ..Default::default()
+            });
+            state.add_data("x");
+            state.set_seed(9);
+            let res = state
+                .run_query(&format!("{} / all", entries.join(";")))
+                .unwrap();
+            let mut res = res.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+            // the data and separators are kept
+            assert_eq!(state.separators().entry, ";");
+            assert!(res.iter().any(|s| s == "x"));
+            res.retain(|s| s != "x");
+            let mut fresh = State::with_algorithm(algorithm, Some(9));
+            fresh.add_data("x");
+            let expected = fresh
+                .run_query(&format!("{} / all", entries.join(",")))
+                .unwrap();
+            let mut expected = expected.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            expected.retain(|s| s != "x");
+            assert_eq!(res, expected);
+
+            state.reseed_from_entropy();
+            assert_eq!(state.rng.algorithm(), algorithm);
+        }
+    }
+
+    #[test]
+    fn byte_stream() {
+        // a die is 1 + the fraction of a u32 times the sides
+        let half = 0x8000_0000u32.to_le_bytes();
+        let two_thirds = 0xAAAA_AAABu32.to_le_bytes();
+        let bytes = [half, two_thirds].concat();
+        let mut state = State::with_bytes(bytes.clone());
+        let res = state.run_query("2d6").unwrap();
+        assert_eq!(crate::ansi::strip(&res[0].to_string()), "2d6: 9");
+        assert_eq!(state.draws(), 2);
+
+        // the same bytes, the same results
+        let mut state = State::with_bytes(bytes);
+        assert_eq!(state.eval_expression("1d6").unwrap().value(), "4");
+        assert_eq!(state.eval_expression("1d6").unwrap().value(), "5");
+
+        let res = state.run_query("1d6");
+        assert!(matches!(res, Err(crate::Error::Rng(_))));
+        assert!(State::with_bytes([]).run_query("a, b").is_err());
+        assert!(State::with_bytes([]).run_query("a").is_ok());
+    }
+
+    #[test]
+    fn byte_stream_exhausted() {
+        // rejection sampling must end even with no bytes
+        let res = State::with_bytes(vec![]).eval_expression("[0..100 normal]");
+        assert!(matches!(res, Err(crate::Error::Rng(_))));
+        let res = State::with_bytes([1, 2, 3]).run_query("[0, 1) normal, uuid / all");
+        assert!(matches!(res, Err(crate::Error::Rng(_))));
+    }
+
+    #[test]
+    fn draws() {
+        for algorithm in [Algorithm::Pcg, Algorithm::ChaCha] {
+            let mut state = State::with_algorithm(algorithm, Some(3));
+            // one for each die, a single entry is not a choice
+            state.run_query("2d6").unwrap();
+            assert_eq!(state.draws(), 2);
+            state.run_query("4d6").unwrap();
+            assert_eq!(state.draws(), 6);
+            state.run_query("a, b").unwrap();
+            assert!(state.draws() > 6);
+
+            state.set_seed(3);
+            assert_eq!(state.draws(), 0);
+        }
+    }
+}
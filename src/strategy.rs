@@ -0,0 +1,158 @@
+//! [proptest] strategies for expressions
+//!
+//! They generate valid expressions as text, ready to be used in a query. Only
+//! available with the `proptest` feature.
+//!
+//! ```
+//! use proptest::prelude::*;
+//! use rng_query::strategy;
+//!
+//! proptest!(|(roll in strategy::roll())| {
+//!     let output = rng_query::run_query(&roll).unwrap();
+//!     prop_assert_eq!(output.len(), 1);
+//! });
+//! ```
+
+use proptest::prelude::*;
+
+/// Dice rolls, like `3d6!k2+1`
+pub fn roll() -> impl Strategy<Value = String> {
+    let sides = prop_oneof![
+        (1u16..=100).prop_map(|s| s.to_string()),
+        Just("%".to_string()),
+        prop::collection::vec(0u16..=20, 1..8).prop_map(|faces| {
+            let faces = faces.iter().map(u16::to_string).collect::<Vec<_>>();
+            format!("[{}]", faces.join(","))
+        }),
+    ];
+    let reroll = prop_oneof![
+        Just(String::new()),
+        Just("e".to_string()),
+        (prop::bool::ANY, 1u16..=3)
+            .prop_map(|(better, n)| { format!("r{}{n}", if better { 'b' } else { 'o' }) }),
+    ];
+    (
+        1u16..=20,
+        sides,
+        prop::option::of(prop::option::of(1u16..=3)),
+        reroll,
+        prop::option::of((prop::sample::select(vec!["k", "kl", "d", "dh"]), 1u16..=20)),
+        prop_oneof![
+            prop::sample::select(vec!["", "^", "v"]).prop_map(String::from),
+            (
+                prop::sample::select(vec![">", ">=", "<", "<=", "="]),
+                0u16..=20
+            )
+                .prop_map(|(cmp, target)| format!("{cmp}{target}")),
+        ],
+        prop::collection::vec(-20i32..=20, 0..3),
+        prop::option::of(-50i32..=50),
+        prop::option::of(1i32..=20),
+    )
+        .prop_map(
+            |(amount, sides, exploding, reroll, select, reduce, modifiers, percent, modulo)| {
+                let mut s = format!("{amount}d{sides}");
+                match exploding {
+                    Some(None) => s.push('!'),
+                    Some(Some(cap)) => s.push_str(&format!("!x{cap}")),
+                    None => {}
+                }
+                s.push_str(&reroll);
+                if let Some((kind, n)) = select {
+                    s.push_str(&format!("{kind}{}", n.min(amount)));
+                }
+                s.push_str(&reduce);
+                for m in modifiers {
+                    s.push_str(&format!("{m:+}"));
+                }
+                if let Some(p) = percent {
+                    s.push_str(&format!(" {p:+}%"));
+                }
+                if let Some(m) = modulo {
+                    s.push_str(&format!(" % {m}"));
+                }
+                s
+            },
+        )
+}
+
+/// Intervals, both integer and float ones
+pub fn interval() -> impl Strategy<Value = String> {
+    let bracket = |inclusive: bool, open: bool| match (inclusive, open) {
+        (true, true) => '[',
+        (false, true) => '(',
+        (true, false) => ']',
+        (false, false) => ')',
+    };
+    let int = (
+        -1000i64..1000,
+        2i64..1000,
+        prop::bool::ANY,
+        prop::bool::ANY,
+        prop::option::of((1i64..10, 0i64..10)),
+    )
+        .prop_map(move |(start, width, low_inc, high_inc, step)| {
+            let end = start + width;
+            match step {
+                Some((step, offset)) => {
+                    // inclusive, so there is always a number
+                    let offset = offset % step.min(width);
+                    format!("[{start}..{end} step {step} offset {offset}]")
+                }
+                None => format!(
+                    "{}{start}..{end}{}",
+                    bracket(low_inc, true),
+                    bracket(high_inc, false)
+                ),
+            }
+        });
+    let float = (
+        -1000i32..1000,
+        1i32..1000,
+        0u8..100,
+        prop::bool::ANY,
+        prop::bool::ANY,
+    )
+        .prop_map(move |(start, width, decimals, low_inc, high_inc)| {
+            let end = start + width;
+            format!(
+                "{}{start}.{decimals}, {end}{}",
+                bracket(low_inc, true),
+                bracket(high_inc, false)
+            )
+        });
+    (prop_oneof![int, float], prop::bool::ANY).prop_map(|(mut s, normal)| {
+        if normal {
+            s.insert_str(s.len() - 1, " normal");
+        }
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{dice::Roll, interval::Interval};
+
+    proptest! {
+        #[test]
+        fn roll_round_trip(s in roll()) {
+            let roll = s.parse::<Roll>().unwrap();
+            let written = crate::ansi::strip(&roll.to_string());
+            prop_assert_eq!(written.parse::<Roll>().unwrap(), roll);
+        }
+
+        #[test]
+        fn interval_round_trip(s in interval()) {
+            let interval = s.parse::<Interval>().unwrap();
+            let written = interval.to_string();
+            prop_assert_eq!(written.parse::<Interval>().unwrap(), interval);
+        }
+
+        #[test]
+        fn run_generated(s in roll(), i in interval()) {
+            let output = crate::run_query(&format!("{s}, {i} / all")).unwrap();
+            prop_assert_eq!(output.len(), 2);
+        }
+    }
+}
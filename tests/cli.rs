@@ -0,0 +1,193 @@
+//! Tests running the `rq` binary
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `rq` with `args` and `stdin`, returning the STDOUT lines
+fn rq(args: &[&str], stdin: &str) -> Vec<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rq"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run rq");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn csv_input_column() {
+    let csv = "id,name,score\n1,\"Smith, John\",10\n2,Jane,20\n";
+    let names = rq(&["--input-format", "csv", "--column", "2", "/ list"], csv);
+    assert_eq!(names, ["name", "Smith, John", "Jane"]);
+
+    let tsv = "a\t1d1\nb\t2d1\n";
+    let values = rq(
+        &[
+            "--input-format",
+            "tsv",
+            "--column",
+            "2",
+            "-e",
+            "-q",
+            "/ all o",
+        ],
+        tsv,
+    );
+    assert_eq!(values, ["1", "2"]);
+
+    // empty first field
+    let tsv = "x\tA\n\tB\n";
+    let names = rq(&["--input-format", "tsv", "--column", "2", "/ list"], tsv);
+    assert_eq!(names, ["A", "B"]);
+}
+
+#[test]
+fn dice_division() {
+    assert_eq!(rq(&["-q", "3d1 div 2"], ""), ["1.5"]);
+    assert_eq!(rq(&["-q", "3d1 div 2, 1d1 / all o"], ""), ["1.5", "1"]);
+    assert_eq!(rq(&["-q", "3d1 div 2 round / 2 r"], ""), ["2", "2"]);
+}
+
+#[test]
+fn completions_query() {
+    // a query, not the completions
+    assert_eq!(rq(&["completions"], ""), ["completions"]);
+    let script = rq(&["--completions", "bash"], "");
+    assert!(script.iter().any(|l| l.contains("--seed")));
+}
+
+#[test]
+fn best_of() {
+    let mut state = rng_query::State::with_seed(7);
+    let rolls = (0..5)
+        .map(|_| state.run_query("1d20+5").unwrap()[0].value())
+        .map(|v| v.parse::<i32>().unwrap())
+        .collect::<Vec<_>>();
+    let best = rq(&["--seed", "7", "--best-of", "5", "-q", "1d20+5"], "");
+    assert_eq!(best, [rolls.iter().max().unwrap().to_string()]);
+
+    let error = rq(&["--best-of", "5", "a, b"], "");
+    assert!(error[0].contains("numeric"), "{error:?}");
+
+    // the entries from stdin are in every run
+    let stdin = (1..=20).map(|n| format!("{n}\n")).collect::<String>();
+    let best = rq(&["--best-of", "3", "-e", "-q"], &stdin);
+    let best = best[0].parse::<u32>().unwrap();
+    assert!((1..=20).contains(&best), "{best}");
+}
+
+#[test]
+fn weights_file() {
+    let path = std::env::temp_dir().join(format!("rq-weights-{}.txt", std::process::id()));
+    std::fs::write(&path, "3\tgoblin\n\n1\torc\n0.0\tdragon\n").unwrap();
+    let path = path.to_str().unwrap();
+
+    let draws = rq(&["--seed", "1", "--weights", path, "-q", "/ 4000 r"], "");
+    let goblins = draws.iter().filter(|d| *d == "goblin").count();
+    let orcs = draws.iter().filter(|d| *d == "orc").count();
+    assert_eq!(goblins + orcs, 4000, "a dragon was drawn");
+    assert!(goblins.abs_diff(3000) < 150, "{goblins} goblins of 4000");
+
+    // also with the entries from stdin, which have weight 1
+    let list = rq(&["--weights", path, "/ list"], "elf\n");
+    assert_eq!(list, ["goblin", "orc", "dragon", "elf"]);
+
+    std::fs::write(path, "3\tgoblin\nmany\torc\n").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rq"))
+        .args(["--weights", path, "/ 1"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2"), "{stderr}");
+}
+
+#[test]
+fn min_max() {
+    for seed in 0..50 {
+        let seed = seed.to_string();
+        let roll = rq(&["--seed", &seed, "--min", "15", "-q", "1d20"], "");
+        let roll = roll[0].parse::<u32>().unwrap();
+        assert!((15..=20).contains(&roll), "{roll}");
+
+        let roll = rq(
+            &["--seed", &seed, "--min", "3", "--max", "4", "-q", "1d6"],
+            "",
+        );
+        let roll = roll[0].parse::<u32>().unwrap();
+        assert!((3..=4).contains(&roll), "{roll}");
+    }
+
+    let error = rq(&["--min", "7", "1d6"], "");
+    assert!(error[0].contains("attempts"), "{error:?}");
+
+    // the entries from stdin are in every attempt
+    let stdin = (1..=20).map(|n| format!("{n}\n")).collect::<String>();
+    for seed in 0..10 {
+        let seed = seed.to_string();
+        let line = rq(&["--seed", &seed, "--min", "15", "-q"], &stdin);
+        let line = line[0].parse::<u32>().unwrap();
+        assert!((15..=20).contains(&line), "{line}");
+    }
+    let error = rq(&["--max", "5", "a, b"], "");
+    assert!(error[0].contains("numeric"), "{error:?}");
+
+    // negative bounds, like the ones in the seed footer
+    let roll = rq(&["--seed", "1", "--max", "-2", "-q", "1d6-5"], "");
+    let roll = roll[0].parse::<i32>().unwrap();
+    assert!((-4..=-2).contains(&roll), "{roll}");
+}
+
+#[test]
+fn advantage() {
+    // values of the pretty lines, the dice and the total
+    let values = |output: Vec<String>| {
+        output
+            .iter()
+            .filter_map(|line| line.split_once(": "))
+            .map(|(_, value)| value.split(' ').next().unwrap().parse::<u32>().unwrap())
+            .collect::<Vec<_>>()
+    };
+    for seed in 0..30 {
+        let seed = seed.to_string();
+        let adv = values(rq(&["--seed", &seed, "--advantage", "-p", "1d20"], ""));
+        let [a, b, total] = adv[..] else {
+            panic!("not two dice: {adv:?}");
+        };
+        assert_eq!(total, a.max(b));
+
+        let dis = rq(&["--seed", &seed, "--disadvantage", "-q", "1d20+5"], "");
+        let dis = dis[0].parse::<u32>().unwrap();
+        assert_eq!(dis, a.min(b) + 5);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rq"))
+        .args(["--advantage", "2d6"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn ascii() {
+    let output = rq(&["--ascii", "--color", "always", "coin, color / all o"], "");
+    assert!(output.iter().all(|line| line.is_ascii()), "{output:?}");
+    let values = rq(&["--ascii", "-q", "coin, color / all o"], "");
+    assert!(["H", "T"].contains(&values[0].as_str()), "{values:?}");
+    assert!(values[1].starts_with('#'), "{values:?}");
+}